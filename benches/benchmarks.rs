@@ -1,6 +1,6 @@
 use simd_csv::ZeroCopyReader;
 use csimdv::default_dialect;
-use csimdv::Parser;
+use csimdv::{Dialect, Parser, QuoteStyle};
 use std::fs::File;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode};
 use csimdv::aligned_buffer::AlignedBuffer;
@@ -18,21 +18,23 @@ fn parse_file_simd_csv_zerocopy(path: &str){
     }
 }
 fn parse_file_csimdv(path: &str){
+    parse_file_csimdv_with(path, default_dialect())
+}
+fn parse_file_csimdv_with(path: &str, dialect: Dialect){
     let file = File::open(path).unwrap();
-    let mut p = Parser::new(default_dialect(), AlignedBuffer::new(&file).unwrap());
-    while let Some(mut record) = p.read_line() {
+    let mut p = Parser::new(dialect, AlignedBuffer::new(&file).unwrap());
+    while let Some(record) = p.read_line() {
         for field in record.iter() {
             let _ = field.len();
         }
     }
 }
 fn collect_paths(basepath: &str) -> Vec<String> {
-    let paths = fs::read_dir(basepath)
+    fs::read_dir(basepath)
         .unwrap()
         .filter_map(|e| e.ok())
         .map(|e| e.path().to_string_lossy().into_owned())
-        .collect::<Vec<_>>();
-    return paths
+        .collect::<Vec<_>>()
 }
 fn comparison_benchmark(c: &mut Criterion) {
     let paths = collect_paths("examples");
@@ -47,7 +49,22 @@ fn comparison_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+fn quote_style_benchmark(c: &mut Criterion) {
+    let paths = collect_paths("examples");
+    let mut group = c.benchmark_group("Quote Style");
+    group.sampling_mode(SamplingMode::Flat);
+    for path in paths.iter() {
+        let metadata = fs::metadata(path).unwrap();
+        group.throughput(criterion::Throughput::Bytes(metadata.len()));
+        group.bench_with_input(BenchmarkId::new("necessary", path), path, |c, p| c.iter(|| parse_file_csimdv(p)));
+        group.bench_with_input(BenchmarkId::new("never", path), path, |c, p| c.iter(|| {
+            parse_file_csimdv_with(p, Dialect { quoting: QuoteStyle::Never, ..default_dialect() })
+        }));
+    }
+    group.finish();
+}
+
 criterion_group!(name = benches;
                  config = Criterion::default().measurement_time(Duration::from_secs(50));
-                 targets = comparison_benchmark);
+                 targets = comparison_benchmark, quote_style_benchmark);
 criterion_main!(benches);
\ No newline at end of file
@@ -5,6 +5,8 @@ pub struct AlignedBuffer {
     mmap: Mmap,
     start: usize,
     line_start: usize,
+    // zero-padded copy of the last, partial chunk of the file
+    tail: [u8; CHUNK_SIZE],
 }
 
 impl AlignedBuffer {
@@ -16,25 +18,61 @@ impl AlignedBuffer {
             mmap,
             start: 0,
             line_start: 0,
+            tail: [0; CHUNK_SIZE],
         })
     }
 
-    pub fn get_chunk(&mut self) -> (&[u8], usize) {
-        let n = CHUNK_SIZE.min(self.mmap.len() - self.start);
-        return (&self.mmap[self.start..self.start + n], n);
+    /// The next full chunk starting at the current position, with the number of valid bytes in it.
+    ///
+    /// Near the end of the file fewer than `CHUNK_SIZE` bytes are left, and loading a whole chunk
+    /// straight from the map could read past it (and fault, if the file ends on a page boundary).
+    /// Those bytes are copied into a zeroed block instead, so callers can always do full loads and
+    /// only need to mask off the bits past `n`.
+    pub fn get_chunk(&mut self) -> (&[u8; CHUNK_SIZE], usize) {
+        let rest = &self.mmap[self.start..];
+        if let Some(chunk) = rest.first_chunk() {
+            return (chunk, CHUNK_SIZE);
+        }
+        self.tail = [0; CHUNK_SIZE];
+        self.tail[..rest.len()].copy_from_slice(rest);
+        (&self.tail, rest.len())
     }
 
     pub fn start_line(&mut self) {
         self.line_start = self.start;
     }
 
-    pub fn get_line_slice(&mut self) -> &[u8] {
-        let ret = &self.mmap[self.line_start..self.start];
-        if self.mmap[self.start] == b'\r' {
-            self.start += 1;
-        }
-        self.start += 1;
-        ret
+    /// Offset of the current line from the start of the file.
+    pub fn line_start(&self) -> usize {
+        self.line_start
+    }
+
+    /// Ends the current line after `len` bytes, returning them and skipping the terminator that
+    /// follows.
+    pub fn get_line_slice(&mut self, len: usize, terminator_len: usize) -> &[u8] {
+        let end = self.line_start + len;
+        self.start = (end + terminator_len).min(self.mmap.len());
+        &self.mmap[self.line_start..end]
+    }
+
+    /// The current line and everything after it.
+    pub fn line_rest(&self) -> &[u8] {
+        &self.mmap[self.line_start..]
+    }
+
+    /// Everything from the current position to the end of the file.
+    pub fn remaining(&self) -> &[u8] {
+        &self.mmap[self.start..]
+    }
+
+    /// The byte `offset` bytes into the current line, if the file is that long.
+    pub fn line_byte(&self, offset: usize) -> Option<u8> {
+        self.mmap.get(self.line_start + offset).copied()
+    }
+
+    /// The current line up to the end of the file, for a last line without a newline.
+    pub fn get_final_line_slice(&self) -> &[u8] {
+        &self.mmap[self.line_start..self.start]
     }
 
     pub fn consume(&mut self, amt: usize) {
@@ -68,5 +106,20 @@ mod buftests {
         let (chunk, valid_bytes) = buf.get_chunk();
         assert_eq!(valid_bytes, 1);
         assert_eq!(&chunk[0..1], b"\n");
+        // the rest of a partial chunk is zeroed, not read from past the end of the file
+        assert!(chunk[1..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_bufread_page_boundary() {
+        let data = "a,".repeat(2048);
+        let mut buf = reader_from_str(&data);
+        buf.consume(4096 - 10);
+        let (chunk, valid_bytes) = buf.get_chunk();
+        assert_eq!(valid_bytes, 10);
+        assert_eq!(&chunk[..10], b"a,a,a,a,a,");
+        assert_eq!(chunk[10..], [0; 54]);
+        buf.consume(10);
+        assert_eq!(buf.get_chunk().1, 0);
     }
 }
\ No newline at end of file
@@ -1,153 +1,682 @@
-#[cfg(all(target_arch = "x86_64", target_feature = "pclmulqdq"))]
-pub(crate) mod prefix_xor {
-    pub fn clmul64(a: u64, b:u64) -> u64{
-        unsafe {
-            use core::arch::x86_64::*;
-            let va = _mm_set_epi64x(0, a as i64);
-            let vb = _mm_set_epi64x(0, b as i64);
-            let r = _mm_cvtsi128_si64(_mm_clmulepi64_si128(va, vb, 0x00)) as u64;
-            r as u64
+use crate::Dialect;
+use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+
+/// The whitespace bytes other than the space, which is compared for `skipinitialspace` anyway.
+const WHITESPACE: [u8; 4] = *b"\t\n\x0C\r";
+
+/// Per-chunk bitmasks produced by a `Classifier`, bit i describing byte i of the chunk.
+pub(crate) struct Masks {
+    /// The delimiter's first byte.
+    pub delimiter: u64,
+    /// The delimiter's other bytes in order, zero past its length.
+    pub delimiter_rest: [u64; MAX_DELIMITER_LEN - 1],
+    pub quote: u64,
+    /// The terminator's byte, `\n` unless the dialect uses a custom one.
+    pub newline: u64,
+    /// Zero unless the terminator involves `\r`.
+    pub carriage_return: u64,
+    /// Spaces that may be skipped at the start of a field, zero unless `skipinitialspace` is set.
+    pub space: u64,
+    /// ASCII whitespace as `u8::is_ascii_whitespace` defines it, zero unless the dialect trims
+    /// fields.
+    pub whitespace: u64,
+    /// Zero unless the dialect has an escape character.
+    pub escape: u64,
+    /// Bytes >= 0x80, which only occur in non-ASCII UTF-8. Zero unless the dialect validates UTF-8.
+    pub non_ascii: u64,
+}
+
+/// The instruction set used to classify chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// One 512-bit compare per character class, needs AVX-512F and AVX-512BW.
+    Avx512,
+    /// Two 256-bit compares per character class.
+    Avx2,
+    /// Four 128-bit compares per character class, for x86_64 CPUs without AVX.
+    Sse,
+    /// Table lookups on interleaved 128-bit loads.
+    Neon,
+    /// Portable SWAR fallback, available everywhere.
+    Scalar,
+}
+
+impl Backend {
+    /// All backends, fastest first.
+    pub(crate) const PREFERENCE: [Backend; 5] = [Backend::Avx512, Backend::Avx2, Backend::Sse, Backend::Neon, Backend::Scalar];
+
+    /// The fastest backend supported by the running CPU.
+    pub fn detect() -> Backend {
+        Self::PREFERENCE.into_iter().find(|backend| backend.is_supported()).unwrap()
+    }
+
+    /// Whether the running CPU (and the target this was compiled for) can use this backend.
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse => is_x86_feature_detected!("sse2"),
+            #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+            Backend::Neon => true,
+            Backend::Scalar => true,
+            _ => false,
+        }
+    }
+}
+
+// boxing the big variants would put a pointer chase in front of every chunk
+#[allow(clippy::large_enum_variant)]
+enum AnyKernel {
+    #[cfg(target_arch = "x86_64")]
+    Avx512(avx512::Classifier),
+    #[cfg(target_arch = "x86_64")]
+    Avx2(avx2::Classifier),
+    #[cfg(target_arch = "x86_64")]
+    Sse(sse::Classifier),
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    Neon(neon::Classifier),
+    Scalar(scalar::Classifier),
+}
+
+#[derive(Clone, Copy)]
+enum Clmul {
+    #[cfg(target_arch = "x86_64")]
+    Pclmulqdq,
+    #[cfg(target_arch = "aarch64")]
+    Pmull,
+    Software,
+}
+
+/// Classifies 64 byte chunks with the backend picked when it was created.
+pub(crate) struct Classifier {
+    backend: Backend,
+    kernel: AnyKernel,
+    clmul: Clmul,
+}
+
+/// One backend's classifier, as a type.
+///
+/// A loop that is generic over the kernel gets compiled once per backend with its `classify`
+/// inlined, instead of matching on the backend for every chunk and carrying the code of all of
+/// them.
+pub(crate) trait Kernel {
+    /// Classifies `chunk` with the kernel of `classifier`, which must have been built for this
+    /// backend.
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks;
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Kernel for avx512::Classifier {
+    #[inline(always)]
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        let AnyKernel::Avx512(kernel) = &classifier.kernel else {
+            unreachable!("the classifier uses the {:?} backend", classifier.backend)
+        };
+        // SAFETY: the kernel was only built after checking its features are available
+        unsafe { kernel.classify(chunk) }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Kernel for avx2::Classifier {
+    #[inline(always)]
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        let AnyKernel::Avx2(kernel) = &classifier.kernel else {
+            unreachable!("the classifier uses the {:?} backend", classifier.backend)
+        };
+        // SAFETY: the kernel was only built after checking its features are available
+        unsafe { kernel.classify(chunk) }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Kernel for sse::Classifier {
+    #[inline(always)]
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        let AnyKernel::Sse(kernel) = &classifier.kernel else {
+            unreachable!("the classifier uses the {:?} backend", classifier.backend)
+        };
+        // SAFETY: the kernel was only built after checking its features are available
+        unsafe { kernel.classify(chunk) }
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+impl Kernel for neon::Classifier {
+    #[inline(always)]
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        let AnyKernel::Neon(kernel) = &classifier.kernel else {
+            unreachable!("the classifier uses the {:?} backend", classifier.backend)
+        };
+        kernel.classify(chunk)
+    }
+}
+
+impl Kernel for scalar::Classifier {
+    #[inline(always)]
+    fn classify(classifier: &Classifier, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        let AnyKernel::Scalar(kernel) = &classifier.kernel else {
+            unreachable!("the classifier uses the {:?} backend", classifier.backend)
+        };
+        kernel.classify(chunk)
+    }
+}
+
+impl Classifier {
+    /// Panics if `backend` isn't supported by the running CPU.
+    pub fn with_backend(dialect: &Dialect, backend: Backend) -> Self {
+        assert!(backend.is_supported(), "the {:?} backend is not supported on this CPU", backend);
+        // SAFETY: is_supported checked that the CPU has the features each kernel is compiled for
+        let kernel = match backend {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => AnyKernel::Avx512(unsafe { avx512::Classifier::new(dialect) }),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => AnyKernel::Avx2(unsafe { avx2::Classifier::new(dialect) }),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse => AnyKernel::Sse(unsafe { sse::Classifier::new(dialect) }),
+            #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+            Backend::Neon => AnyKernel::Neon(neon::Classifier::new(dialect)),
+            _ => AnyKernel::Scalar(scalar::Classifier::new(dialect)),
+        };
+        // the scalar backend stays portable all the way through, so it can be tested against
+        let clmul = match backend {
+            Backend::Scalar => Clmul::Software,
+            #[cfg(target_arch = "x86_64")]
+            _ if is_x86_feature_detected!("pclmulqdq") => Clmul::Pclmulqdq,
+            #[cfg(target_arch = "aarch64")]
+            _ if std::arch::is_aarch64_feature_detected!("aes") => Clmul::Pmull,
+            _ => Clmul::Software,
+        };
+        Self { backend, kernel, clmul }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    #[inline(always)]
+    pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+        // SAFETY: the kernel was only built after checking its features are available
+        match &self.kernel {
+            #[cfg(target_arch = "x86_64")]
+            AnyKernel::Avx512(c) => unsafe { c.classify(chunk) },
+            #[cfg(target_arch = "x86_64")]
+            AnyKernel::Avx2(c) => unsafe { c.classify(chunk) },
+            #[cfg(target_arch = "x86_64")]
+            AnyKernel::Sse(c) => unsafe { c.classify(chunk) },
+            #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+            AnyKernel::Neon(c) => c.classify(chunk),
+            AnyKernel::Scalar(c) => c.classify(chunk),
         }
     }
 
+    #[inline(always)]
+    pub fn clmul64(&self, a: u64, b: u64) -> u64 {
+        // SAFETY: the hardware variants are only picked after checking their features
+        match self.clmul {
+            #[cfg(target_arch = "x86_64")]
+            Clmul::Pclmulqdq => unsafe { prefix_xor::clmul64(a, b) },
+            #[cfg(target_arch = "aarch64")]
+            Clmul::Pmull => unsafe { prefix_xor::clmul64(a, b) },
+            Clmul::Software => scalar::clmul64(a, b),
+        }
+    }
 }
 
-#[cfg(all(target_arch = "aarch64", target_feature = "aes"))]
+#[cfg(target_arch = "x86_64")]
 pub(crate) mod prefix_xor {
+    use core::arch::x86_64::*;
+
+    #[target_feature(enable = "pclmulqdq")]
     pub fn clmul64(a: u64, b:u64) -> u64{
-        unsafe {
-            use core::arch::aarch64::*;
-            let r = vmull_p64(a, b);
-            r as u64
-        }
+        let va = _mm_set_epi64x(0, a as i64);
+        let vb = _mm_set_epi64x(0, b as i64);
+        let r = _mm_cvtsi128_si64(_mm_clmulepi64_si128(va, vb, 0x00)) as u64;
+        r as u64
     }
 
 }
 
+#[cfg(target_arch = "aarch64")]
+pub(crate) mod prefix_xor {
+    use core::arch::aarch64::*;
+
+    #[target_feature(enable = "neon,aes")]
+    pub fn clmul64(a: u64, b:u64) -> u64{
+        let r = vmull_p64(a, b);
+        r as u64
+    }
 
+}
 
-#[cfg(all(target_arch = "x86_64", target_feature = "avx512f"))]
-pub(crate) mod simd {
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod avx512 {
     use core::arch::x86_64::*;
+    use crate::{Dialect, Trim};
+    use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+    use super::{Masks, WHITESPACE};
 
     pub struct Classifier {
-        comma_splat: __m512i,
+        delimiter_splat: [__m512i; MAX_DELIMITER_LEN],
+        delimiter_len: usize,
+        space_splat: __m512i,
         newline_splat: __m512i,
         quote_splat: __m512i,
-        return_splat: __m512i
+        return_splat: __m512i,
+        escape_splat: __m512i,
+        whitespace_splat: [__m512i; 4],
+        // whether quoting is enabled
+        quote_enable: bool,
+        // whether skipinitialspace is set
+        space_enable: bool,
+        // whether fields are trimmed
+        whitespace_enable: bool,
+        // whether records are validated as UTF-8
+        utf8_enable: bool,
+        // whether the terminator involves \r
+        return_enable: bool,
+        // whether there is an escape character
+        escape_enable: bool,
     }
     impl Classifier {
-        pub fn new() -> Self {
+        #[target_feature(enable = "avx512f,avx512bw")]
+        pub fn new(dialect: &Dialect) -> Self {
+            let delimiter = dialect.delimiter_bytes();
+            let mut delimiter_splat = [_mm512_setzero_si512(); MAX_DELIMITER_LEN];
+            for (splat, &b) in delimiter_splat.iter_mut().zip(delimiter) {
+                *splat = _mm512_set1_epi8(b as i8);
+            }
             Self {
-                comma_splat: unsafe { _mm512_set1_epi8(',' as i8) },
-                newline_splat: unsafe { _mm512_set1_epi8('\n' as i8) },
-                return_splat: unsafe { _mm512_set1_epi8('\r' as i8) },
-                quote_splat: unsafe { _mm512_set1_epi8('\"' as i8) },
+                space_splat: _mm512_set1_epi8(' ' as i8),
+                space_enable: dialect.skipinitialspace,
+                whitespace_splat: WHITESPACE.map(|b| _mm512_set1_epi8(b as i8)),
+                whitespace_enable: dialect.trim != Trim::None,
+                utf8_enable: dialect.validate_utf8,
+                delimiter_splat,
+                delimiter_len: delimiter.len(),
+                newline_splat: _mm512_set1_epi8(dialect.terminator_bytes().0 as i8),
+                return_splat: _mm512_set1_epi8('\r' as i8),
+                escape_splat: _mm512_set1_epi8(dialect.escapechar_byte().unwrap_or(0) as i8),
+                quote_splat: _mm512_set1_epi8(dialect.quotechar_byte().unwrap_or(0) as i8),
+                quote_enable: dialect.quotechar_byte().is_some(),
+                return_enable: dialect.terminator_bytes().1,
+                escape_enable: dialect.escapechar_byte().is_some(),
             }
         }
 
-        #[inline(always)]
-        pub fn classify(&self, chunk: &[u8]) -> (u64, u64, u64) {
-            unsafe {
-                let chunk = _mm512_loadu_si512(chunk.as_ptr() as *const __m512i);
-                (_mm512_cmpeq_epi8_mask(chunk, self.comma_splat),
-                 _mm512_cmpeq_epi8_mask(chunk, self.quote_splat),
-                 _mm512_cmpeq_epi8_mask(chunk, self.newline_splat) | _mm512_cmpeq_epi8_mask(chunk, self.return_splat))
+        #[inline]
+        #[target_feature(enable = "avx512f,avx512bw")]
+        pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+            let chunk = unsafe { _mm512_loadu_si512(chunk.as_ptr() as *const __m512i) };
+            let mut delimiter_rest = [0; MAX_DELIMITER_LEN - 1];
+            for (mask, &splat) in delimiter_rest.iter_mut().zip(&self.delimiter_splat[1..]).take(self.delimiter_len - 1) {
+                *mask = _mm512_cmpeq_epi8_mask(chunk, splat);
+            }
+            // trimming counts spaces as whitespace too
+            let spaces = if self.space_enable || self.whitespace_enable { _mm512_cmpeq_epi8_mask(chunk, self.space_splat) } else { 0 };
+            Masks {
+                delimiter: _mm512_cmpeq_epi8_mask(chunk, self.delimiter_splat[0]),
+                delimiter_rest,
+                quote: if self.quote_enable { _mm512_cmpeq_epi8_mask(chunk, self.quote_splat) } else { 0 },
+                newline: _mm512_cmpeq_epi8_mask(chunk, self.newline_splat),
+                carriage_return: if self.return_enable { _mm512_cmpeq_epi8_mask(chunk, self.return_splat) } else { 0 },
+                space: if self.space_enable { spaces } else { 0 },
+                whitespace: if self.whitespace_enable {
+                    self.whitespace_splat.iter().fold(spaces, |mask, &splat| mask | _mm512_cmpeq_epi8_mask(chunk, splat))
+                } else {
+                    0
+                },
+                escape: if self.escape_enable { _mm512_cmpeq_epi8_mask(chunk, self.escape_splat) } else { 0 },
+                non_ascii: if self.utf8_enable { _mm512_movepi8_mask(chunk) } else { 0 },
             }
         }
     }
 }
 
-#[cfg(all(target_arch = "x86_64", target_feature = "avx2", not(target_feature="avx512f")))]
-pub(crate) mod simd {
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod avx2 {
     use core::arch::x86_64::*;
+    use crate::{Dialect, Trim};
+    use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+    use super::{Masks, WHITESPACE};
 
     pub struct Classifier {
-        comma_splat: (__m256i, __m256i),
+        delimiter_splat: [(__m256i, __m256i); MAX_DELIMITER_LEN],
+        delimiter_len: usize,
+        space_splat: (__m256i, __m256i),
         newline_splat: (__m256i, __m256i),
         quote_splat: (__m256i, __m256i),
         return_splat: (__m256i, __m256i),
+        escape_splat: (__m256i, __m256i),
+        whitespace_splat: [(__m256i, __m256i); 4],
+        // whether quoting is enabled
+        quote_enable: bool,
+        // whether skipinitialspace is set
+        space_enable: bool,
+        // whether fields are trimmed
+        whitespace_enable: bool,
+        // whether records are validated as UTF-8
+        utf8_enable: bool,
+        // whether the terminator involves \r
+        return_enable: bool,
+        // whether there is an escape character
+        escape_enable: bool,
     }
 
+    #[inline]
+    #[target_feature(enable = "avx2")]
     pub fn load_simd(p: *const u8) -> (__m256i, __m256i) {
         unsafe {
-            use core::arch::x86_64::*;
             let r0 = _mm256_loadu_si256(p as *const __m256i);
             let r1 = _mm256_loadu_si256(p.add(32) as *const __m256i);
             (r0, r1)
         }
     }
 
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    fn lane_eq_bitmask(a: (__m256i, __m256i), b: (__m256i, __m256i)) -> u64 {
+        let cmp1 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(a.0, b.0)) as u32 as u64;
+        let cmp2 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(a.1, b.1)) as u32 as u64;
+        cmp1 | cmp2 << 32
+    }
+
+    /// The top bit of each byte, no compare needed.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    fn high_bitmask(a: (__m256i, __m256i)) -> u64 {
+        _mm256_movemask_epi8(a.0) as u32 as u64 | (_mm256_movemask_epi8(a.1) as u32 as u64) << 32
+    }
+
     impl Classifier {
-        #[inline(always)]
-        pub fn new() -> Self {
-            unsafe {
-                Self {
-                    comma_splat: load_simd([b','; 64].as_ptr()),
-                    newline_splat: load_simd([b'\n'; 64].as_ptr()),
-                    return_splat: load_simd([b'\r'; 64].as_ptr()),
-                    quote_splat: load_simd([b'\"'; 64].as_ptr()),
-                }
+        #[target_feature(enable = "avx2")]
+        pub fn new(dialect: &Dialect) -> Self {
+            let delimiter = dialect.delimiter_bytes();
+            let mut delimiter_splat = [load_simd([0; 64].as_ptr()); MAX_DELIMITER_LEN];
+            for (splat, &b) in delimiter_splat.iter_mut().zip(delimiter) {
+                *splat = load_simd([b; 64].as_ptr());
+            }
+            Self {
+                delimiter_splat,
+                delimiter_len: delimiter.len(),
+                newline_splat: load_simd([dialect.terminator_bytes().0; 64].as_ptr()),
+                return_splat: load_simd([b'\r'; 64].as_ptr()),
+                escape_splat: load_simd([dialect.escapechar_byte().unwrap_or(0); 64].as_ptr()),
+                quote_splat: load_simd([dialect.quotechar_byte().unwrap_or(0); 64].as_ptr()),
+                quote_enable: dialect.quotechar_byte().is_some(),
+                return_enable: dialect.terminator_bytes().1,
+                escape_enable: dialect.escapechar_byte().is_some(),
+                space_splat: load_simd([b' '; 64].as_ptr()),
+                space_enable: dialect.skipinitialspace,
+                whitespace_splat: WHITESPACE.map(|b| load_simd([b; 64].as_ptr())),
+                whitespace_enable: dialect.trim != Trim::None,
+                utf8_enable: dialect.validate_utf8,
             }
         }
 
-        #[inline(always)]
-        pub fn classify(&self, chunk: &[u8]) -> (u64, u64, u64) {
-            unsafe {
-                fn lane_eq_bitmask(a: (__m256i, __m256i), b: (__m256i, __m256i)) -> u64 {
-                    unsafe {
-                        let cmp1 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(a.0, b.0)) as u32 as u64;
-                        let cmp2 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(a.1, b.1)) as u32 as u64;
-                        (cmp1 | cmp2 << 32)
-                    }
-                }
-                let chunk = load_simd(chunk.as_ptr());
-                (lane_eq_bitmask(chunk, self.comma_splat), lane_eq_bitmask(chunk, self.quote_splat), lane_eq_bitmask(chunk, self.newline_splat) | lane_eq_bitmask(chunk, self.return_splat))
+        #[inline]
+        #[target_feature(enable = "avx2")]
+        pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+            let chunk = load_simd(chunk.as_ptr());
+            let mut delimiter_rest = [0; MAX_DELIMITER_LEN - 1];
+            for (mask, &splat) in delimiter_rest.iter_mut().zip(&self.delimiter_splat[1..]).take(self.delimiter_len - 1) {
+                *mask = lane_eq_bitmask(chunk, splat);
+            }
+            // trimming counts spaces as whitespace too
+            let spaces = if self.space_enable || self.whitespace_enable { lane_eq_bitmask(chunk, self.space_splat) } else { 0 };
+            Masks {
+                delimiter: lane_eq_bitmask(chunk, self.delimiter_splat[0]),
+                delimiter_rest,
+                quote: if self.quote_enable { lane_eq_bitmask(chunk, self.quote_splat) } else { 0 },
+                newline: lane_eq_bitmask(chunk, self.newline_splat),
+                carriage_return: if self.return_enable { lane_eq_bitmask(chunk, self.return_splat) } else { 0 },
+                space: if self.space_enable { spaces } else { 0 },
+                whitespace: if self.whitespace_enable {
+                    self.whitespace_splat.iter().fold(spaces, |mask, &splat| mask | lane_eq_bitmask(chunk, splat))
+                } else {
+                    0
+                },
+                escape: if self.escape_enable { lane_eq_bitmask(chunk, self.escape_splat) } else { 0 },
+                non_ascii: if self.utf8_enable { high_bitmask(chunk) } else { 0 },
             }
         }
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod sse {
+    use core::arch::x86_64::*;
+    use crate::{Dialect, Trim};
+    use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+    use super::{Masks, WHITESPACE};
+
+    type Block = (__m128i, __m128i, __m128i, __m128i);
+
+    pub struct Classifier {
+        delimiter_splat: [__m128i; MAX_DELIMITER_LEN],
+        delimiter_len: usize,
+        space_splat: __m128i,
+        newline_splat: __m128i,
+        quote_splat: __m128i,
+        return_splat: __m128i,
+        escape_splat: __m128i,
+        whitespace_splat: [__m128i; 4],
+        // whether quoting is enabled
+        quote_enable: bool,
+        // whether skipinitialspace is set
+        space_enable: bool,
+        // whether fields are trimmed
+        whitespace_enable: bool,
+        // whether records are validated as UTF-8
+        utf8_enable: bool,
+        // whether the terminator involves \r
+        return_enable: bool,
+        // whether there is an escape character
+        escape_enable: bool,
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    pub fn load_simd(p: *const u8) -> Block {
+        unsafe {
+            (_mm_loadu_si128(p as *const __m128i),
+             _mm_loadu_si128(p.add(16) as *const __m128i),
+             _mm_loadu_si128(p.add(32) as *const __m128i),
+             _mm_loadu_si128(p.add(48) as *const __m128i))
+        }
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn lane_eq_bitmask(a: Block, b: __m128i) -> u64 {
+        let cmp0 = _mm_movemask_epi8(_mm_cmpeq_epi8(a.0, b)) as u16 as u64;
+        let cmp1 = _mm_movemask_epi8(_mm_cmpeq_epi8(a.1, b)) as u16 as u64;
+        let cmp2 = _mm_movemask_epi8(_mm_cmpeq_epi8(a.2, b)) as u16 as u64;
+        let cmp3 = _mm_movemask_epi8(_mm_cmpeq_epi8(a.3, b)) as u16 as u64;
+        cmp0 | cmp1 << 16 | cmp2 << 32 | cmp3 << 48
+    }
+
+    /// The top bit of each byte, no compare needed.
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn high_bitmask(a: Block) -> u64 {
+        let m0 = _mm_movemask_epi8(a.0) as u16 as u64;
+        let m1 = _mm_movemask_epi8(a.1) as u16 as u64;
+        let m2 = _mm_movemask_epi8(a.2) as u16 as u64;
+        let m3 = _mm_movemask_epi8(a.3) as u16 as u64;
+        m0 | m1 << 16 | m2 << 32 | m3 << 48
+    }
+
+    impl Classifier {
+        #[target_feature(enable = "sse2")]
+        pub fn new(dialect: &Dialect) -> Self {
+            let delimiter = dialect.delimiter_bytes();
+            let mut delimiter_splat = [_mm_setzero_si128(); MAX_DELIMITER_LEN];
+            for (splat, &b) in delimiter_splat.iter_mut().zip(delimiter) {
+                *splat = _mm_set1_epi8(b as i8);
+            }
+            Self {
+                delimiter_splat,
+                delimiter_len: delimiter.len(),
+                newline_splat: _mm_set1_epi8(dialect.terminator_bytes().0 as i8),
+                return_splat: _mm_set1_epi8('\r' as i8),
+                escape_splat: _mm_set1_epi8(dialect.escapechar_byte().unwrap_or(0) as i8),
+                quote_splat: _mm_set1_epi8(dialect.quotechar_byte().unwrap_or(0) as i8),
+                quote_enable: dialect.quotechar_byte().is_some(),
+                return_enable: dialect.terminator_bytes().1,
+                escape_enable: dialect.escapechar_byte().is_some(),
+                space_splat: _mm_set1_epi8(' ' as i8),
+                space_enable: dialect.skipinitialspace,
+                whitespace_splat: WHITESPACE.map(|b| _mm_set1_epi8(b as i8)),
+                whitespace_enable: dialect.trim != Trim::None,
+                utf8_enable: dialect.validate_utf8,
+            }
+        }
+
+        #[inline]
+        #[target_feature(enable = "sse2")]
+        pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+            let chunk = load_simd(chunk.as_ptr());
+            let mut delimiter_rest = [0; MAX_DELIMITER_LEN - 1];
+            for (mask, &splat) in delimiter_rest.iter_mut().zip(&self.delimiter_splat[1..]).take(self.delimiter_len - 1) {
+                *mask = lane_eq_bitmask(chunk, splat);
+            }
+            // trimming counts spaces as whitespace too
+            let spaces = if self.space_enable || self.whitespace_enable { lane_eq_bitmask(chunk, self.space_splat) } else { 0 };
+            Masks {
+                delimiter: lane_eq_bitmask(chunk, self.delimiter_splat[0]),
+                delimiter_rest,
+                quote: if self.quote_enable { lane_eq_bitmask(chunk, self.quote_splat) } else { 0 },
+                newline: lane_eq_bitmask(chunk, self.newline_splat),
+                carriage_return: if self.return_enable { lane_eq_bitmask(chunk, self.return_splat) } else { 0 },
+                space: if self.space_enable { spaces } else { 0 },
+                whitespace: if self.whitespace_enable {
+                    self.whitespace_splat.iter().fold(spaces, |mask, &splat| mask | lane_eq_bitmask(chunk, splat))
+                } else {
+                    0
+                },
+                escape: if self.escape_enable { lane_eq_bitmask(chunk, self.escape_splat) } else { 0 },
+                non_ascii: if self.utf8_enable { high_bitmask(chunk) } else { 0 },
+            }
+        }
+    }
+}
 
 #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-pub(crate) mod simd {
+pub(crate) mod neon {
     use core::arch::aarch64::*;
+    use crate::{Dialect, Trim};
+    use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+    use super::{Masks, WHITESPACE};
 
-    pub const COMMA: u8 = 2;
+    pub const DELIMITER: u8 = 2;
     pub const NEWLINE: u8 = 4;
     pub const QUOTES: u8 = 8;
+    pub const SPACE: u8 = 16;
+    pub const RETURN: u8 = 32;
+    pub const ESCAPE: u8 = 64;
 
-    pub const BYTE_TABLE: [u8; 64] = {
-        let mut out = [0u8; 64];
-        out[0x0A] = NEWLINE;
-        out[0x0D] = NEWLINE;
-        out[0x2C] = COMMA;
-        out[0x22] = QUOTES;
+    // vqtbl4q can only index 64 entries, so the ASCII range is split into a low (0x00..0x40) and a
+    // high (0x40..0x80) table. Bytes >= 0x80 are out of range for both lookups and classify as 0.
+    fn byte_table(dialect: &Dialect) -> [u8; 128] {
+        let mut out = [0u8; 128];
+        let (newline, carriage_return) = dialect.terminator_bytes();
+        if carriage_return {
+            out[0x0D] = RETURN;
+        }
+        out[newline as usize] = NEWLINE;
+        if dialect.skipinitialspace {
+            out[0x20] = SPACE;
+        }
+        if let Some(escape) = dialect.escapechar_byte() {
+            out[escape as usize] = ESCAPE;
+        }
+        if let Some(quote) = dialect.quotechar_byte() {
+            out[quote as usize] = QUOTES;
+        }
+        // longer delimiters are compared byte by byte instead, see `Classifier::delimiter_bytes`
+        if let &[delimiter] = dialect.delimiter_bytes() {
+            out[delimiter as usize] = DELIMITER;
+        }
         out
-    };
+    }
 
     pub struct Classifier {
         bit_select_mask_1: uint8x16_t,
         bit_select_mask_2: uint8x16_t,
         byte_table: uint8x16x4_t,
-        comma_splat: uint8x16_t,
+        high_byte_table: Option<uint8x16x4_t>,
+        high_offset: uint8x16_t,
+        delimiter_splat: uint8x16_t,
         newline_splat: uint8x16_t,
         quote_splat: uint8x16_t,
+        space_splat: uint8x16_t,
+        return_splat: uint8x16_t,
+        escape_splat: uint8x16_t,
+        one_splat: uint8x16_t,
+        // the raw whitespace bytes, which may have another class already
+        whitespace_bytes: [uint8x16_t; 5],
+        // whether quoting is enabled
+        quote_enable: bool,
+        // whether skipinitialspace is set
+        space_enable: bool,
+        // whether the terminator involves \r
+        return_enable: bool,
+        // whether there is an escape character
+        escape_enable: bool,
+        // whether fields are trimmed
+        whitespace_enable: bool,
+        // whether records are validated as UTF-8
+        utf8_enable: bool,
+        // the raw bytes of a multi-byte delimiter, which may repeat or be >= 0x80 and so can't
+        // each get a class of their own in the table
+        delimiter_bytes: [uint8x16_t; MAX_DELIMITER_LEN],
+        delimiter_len: usize,
     }
     impl Classifier {
-        pub fn new() -> Self {
+        pub fn new(dialect: &Dialect) -> Self {
+            let table = byte_table(dialect);
+            let delimiter = dialect.delimiter_bytes();
+            let mut delimiter_bytes = [unsafe { vdupq_n_u8(0) }; MAX_DELIMITER_LEN];
+            for (splat, &b) in delimiter_bytes.iter_mut().zip(delimiter) {
+                *splat = unsafe { vdupq_n_u8(b) };
+            }
+            // only pay for the second lookup when one of the special bytes lives in the high half
+            let high_byte_table = if table[64..].iter().any(|&b| b != 0) {
+                Some(unsafe { vld1q_u8_x4(table[64..].as_ptr()) })
+            } else {
+                None
+            };
             Self {
-                byte_table: unsafe { vld1q_u8_x4(&BYTE_TABLE as *const u8) },
+                byte_table: unsafe { vld1q_u8_x4(table.as_ptr()) },
+                high_byte_table,
+                high_offset: unsafe { vdupq_n_u8(0x40) },
                 bit_select_mask_1: unsafe { vdupq_n_u8(0x55) },
                 bit_select_mask_2: unsafe { vdupq_n_u8(0x33) },
-                comma_splat: unsafe { vdupq_n_u8(COMMA) },
+                delimiter_splat: unsafe { vdupq_n_u8(DELIMITER) },
                 newline_splat: unsafe { vdupq_n_u8(NEWLINE) },
                 quote_splat: unsafe { vdupq_n_u8(QUOTES) },
+                space_splat: unsafe { vdupq_n_u8(SPACE) },
+                return_splat: unsafe { vdupq_n_u8(RETURN) },
+                escape_splat: unsafe { vdupq_n_u8(ESCAPE) },
+                one_splat: unsafe { vdupq_n_u8(1) },
+                whitespace_bytes: [b' ', WHITESPACE[0], WHITESPACE[1], WHITESPACE[2], WHITESPACE[3]].map(|b| unsafe { vdupq_n_u8(b) }),
+                quote_enable: dialect.quotechar_byte().is_some(),
+                space_enable: dialect.skipinitialspace,
+                return_enable: dialect.terminator_bytes().1,
+                escape_enable: dialect.escapechar_byte().is_some(),
+                whitespace_enable: dialect.trim != Trim::None,
+                utf8_enable: dialect.validate_utf8,
+                delimiter_bytes,
+                delimiter_len: delimiter.len(),
             }
         }
 
         #[inline(always)]
-        pub fn classify(&self, chunk: &[u8]) -> (u64, u64, u64) {
+        pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
             unsafe {
                 // load the chunk interleaved (this makes the movemask emulation easier at the end).
                 let chunk = vld4q_u8(chunk.as_ptr());
@@ -156,12 +685,21 @@ pub(crate) mod simd {
                 // vqtbl4q is only twice the the latency of a single register vqtbl (4 vs 2), but it
                 // halves the number of table lookups we need to do (for high and low nibbles), and removes
                 // the need to and them. So in all, it should save us about 4 instructions.
-                let classified = uint8x16x4_t(
+                let mut classified = uint8x16x4_t(
                     vqtbl4q_u8(self.byte_table, chunk.0),
                     vqtbl4q_u8(self.byte_table, chunk.1),
                     vqtbl4q_u8(self.byte_table, chunk.2),
                     vqtbl4q_u8(self.byte_table, chunk.3),
                 );
+                if let Some(high) = self.high_byte_table {
+                    // bytes below 0x40 wrap around to >= 0xC0, which vqtbx leaves untouched
+                    classified = uint8x16x4_t(
+                        vqtbx4q_u8(classified.0, high, vsubq_u8(chunk.0, self.high_offset)),
+                        vqtbx4q_u8(classified.1, high, vsubq_u8(chunk.1, self.high_offset)),
+                        vqtbx4q_u8(classified.2, high, vsubq_u8(chunk.2, self.high_offset)),
+                        vqtbx4q_u8(classified.3, high, vsubq_u8(chunk.3, self.high_offset)),
+                    );
+                }
 
                 let to_bitmask = |input: uint8x16x4_t, s: uint8x16_t| -> u64 {
                     // isolate 01010101 and 23232323
@@ -169,12 +707,290 @@ pub(crate) mod simd {
                     let t1 = vbslq_u8(self.bit_select_mask_1, vceqq_u8(input.2, s), vceqq_u8(input.3, s)); // 23232323...
                     let combined = vbslq_u8(self.bit_select_mask_2, t0, t1); // 01230123...
                     let sum = vshrn_n_s16::<4>(vreinterpretq_s16_u8(combined));
-                    return vget_lane_u64::<0>(vreinterpret_u64_s8(sum));
+                    vget_lane_u64::<0>(vreinterpret_u64_s8(sum))
                 };
-                (to_bitmask(classified, self.comma_splat),
-                        to_bitmask(classified, self.quote_splat),
-                        to_bitmask(classified, self.newline_splat))
+                let mut delimiter_rest = [0; MAX_DELIMITER_LEN - 1];
+                let delimiter = if self.delimiter_len == 1 {
+                    to_bitmask(classified, self.delimiter_splat)
+                } else {
+                    // vld4q interleaved the raw bytes the same way as their classes
+                    for (mask, &splat) in delimiter_rest.iter_mut().zip(&self.delimiter_bytes[1..]).take(self.delimiter_len - 1) {
+                        *mask = to_bitmask(chunk, splat);
+                    }
+                    to_bitmask(chunk, self.delimiter_bytes[0])
+                };
+                Masks {
+                    delimiter,
+                    delimiter_rest,
+                    quote: if self.quote_enable { to_bitmask(classified, self.quote_splat) } else { 0 },
+                    newline: to_bitmask(classified, self.newline_splat),
+                    carriage_return: if self.return_enable { to_bitmask(classified, self.return_splat) } else { 0 },
+                    space: if self.space_enable { to_bitmask(classified, self.space_splat) } else { 0 },
+                    // vld4q interleaved the raw bytes the same way as their classes
+                    whitespace: if self.whitespace_enable {
+                        self.whitespace_bytes.iter().fold(0, |mask, &splat| mask | to_bitmask(chunk, splat))
+                    } else {
+                        0
+                    },
+                    escape: if self.escape_enable { to_bitmask(classified, self.escape_splat) } else { 0 },
+                    // the top bit of each raw byte, shifted down to compare against 1
+                    non_ascii: if self.utf8_enable {
+                        let high = uint8x16x4_t(vshrq_n_u8::<7>(chunk.0), vshrq_n_u8::<7>(chunk.1), vshrq_n_u8::<7>(chunk.2), vshrq_n_u8::<7>(chunk.3));
+                        to_bitmask(high, self.one_splat)
+                    } else {
+                        0
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Portable fallback used when no SIMD backend is available, working on 8 bytes at a time
+/// within a u64 (SWAR).
+pub(crate) mod scalar {
+    use crate::{Dialect, Trim};
+    use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+    use super::{Masks, WHITESPACE};
+
+    const ONES: u64 = 0x0101_0101_0101_0101;
+    const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+    /// Carry-less multiplication of `a` and `b`, keeping the low 64 bits.
+    ///
+    /// One shifted copy of `a` is xored in per set bit of `b`. The parser only ever passes quote
+    /// masks as `b`, which are usually sparse.
+    pub fn clmul64(a: u64, b: u64) -> u64 {
+        let mut b = b;
+        let mut r = 0;
+        while b != 0 {
+            r ^= a << b.trailing_zeros();
+            b &= b - 1;
+        }
+        r
+    }
+
+    /// Position of the first byte equal to `a` or `b`, searching a word at a time.
+    pub fn find_either(haystack: &[u8], a: u8, b: u8) -> Option<usize> {
+        let (a_splat, b_splat) = (splat(a), splat(b));
+        let mut words = haystack.chunks_exact(8);
+        for (i, bytes) in words.by_ref().enumerate() {
+            let word = u64::from_le_bytes(bytes.try_into().unwrap());
+            let found = eq_bits(word, a_splat) | eq_bits(word, b_splat);
+            if found != 0 {
+                return Some(i * 8 + found.trailing_zeros() as usize);
+            }
+        }
+        let tail = words.remainder();
+        tail.iter().position(|&c| c == a || c == b).map(|i| haystack.len() - tail.len() + i)
+    }
+
+    #[inline(always)]
+    fn splat(b: u8) -> u64 {
+        ONES * b as u64
+    }
+
+    /// One bit per byte of `word` that equals the splatted byte, packed into the low 8 bits.
+    #[inline(always)]
+    fn eq_bits(word: u64, splat: u64) -> u64 {
+        let x = word ^ splat;
+        // the high bit of a byte is set iff the byte is zero. Unlike the usual haszero trick this
+        // has no false positives, since the add can't carry across bytes.
+        let zero = !(((x & !HIGHS) + !HIGHS) | x) & HIGHS;
+        high_bits(zero)
+    }
+
+    /// The high bit of each byte of `word`, packed into the low 8 bits.
+    #[inline(always)]
+    fn high_bits(word: u64) -> u64 {
+        // gather the high bits of each byte into the top byte, in order
+        ((word & HIGHS) >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56
+    }
+
+    pub struct Classifier {
+        delimiter_splat: [u64; MAX_DELIMITER_LEN],
+        delimiter_len: usize,
+        newline_splat: u64,
+        return_splat: u64,
+        escape_splat: u64,
+        quote_splat: u64,
+        space_splat: u64,
+        whitespace_splat: [u64; 4],
+        // whether quoting is enabled
+        quote_enable: bool,
+        // whether skipinitialspace is set
+        space_enable: bool,
+        // whether fields are trimmed
+        whitespace_enable: bool,
+        // whether records are validated as UTF-8
+        utf8_enable: bool,
+        // whether the terminator involves \r
+        return_enable: bool,
+        // whether there is an escape character
+        escape_enable: bool,
+    }
+    impl Classifier {
+        pub fn new(dialect: &Dialect) -> Self {
+            let delimiter = dialect.delimiter_bytes();
+            let mut delimiter_splat = [0; MAX_DELIMITER_LEN];
+            for (splatted, &b) in delimiter_splat.iter_mut().zip(delimiter) {
+                *splatted = splat(b);
+            }
+            Self {
+                delimiter_splat,
+                delimiter_len: delimiter.len(),
+                newline_splat: splat(dialect.terminator_bytes().0),
+                return_splat: splat(b'\r'),
+                escape_splat: splat(dialect.escapechar_byte().unwrap_or(0)),
+                quote_splat: splat(dialect.quotechar_byte().unwrap_or(0)),
+                space_splat: splat(b' '),
+                whitespace_splat: WHITESPACE.map(splat),
+                quote_enable: dialect.quotechar_byte().is_some(),
+                return_enable: dialect.terminator_bytes().1,
+                escape_enable: dialect.escapechar_byte().is_some(),
+                space_enable: dialect.skipinitialspace,
+                whitespace_enable: dialect.trim != Trim::None,
+                utf8_enable: dialect.validate_utf8,
+            }
+        }
+
+        #[inline(always)]
+        pub fn classify(&self, chunk: &[u8; CHUNK_SIZE]) -> Masks {
+            let mut masks = Masks { delimiter: 0, delimiter_rest: [0; MAX_DELIMITER_LEN - 1], quote: 0, newline: 0, carriage_return: 0, space: 0, whitespace: 0, escape: 0, non_ascii: 0 };
+            for (i, bytes) in chunk.chunks_exact(8).enumerate() {
+                let word = u64::from_le_bytes(bytes.try_into().unwrap());
+                let shift = i * 8;
+                masks.delimiter |= eq_bits(word, self.delimiter_splat[0]) << shift;
+                for (mask, &splat) in masks.delimiter_rest.iter_mut().zip(&self.delimiter_splat[1..]).take(self.delimiter_len - 1) {
+                    *mask |= eq_bits(word, splat) << shift;
+                }
+                masks.newline |= eq_bits(word, self.newline_splat) << shift;
+                // the classes the dialect doesn't use are left at zero
+                if self.quote_enable {
+                    masks.quote |= eq_bits(word, self.quote_splat) << shift;
+                }
+                if self.return_enable {
+                    masks.carriage_return |= eq_bits(word, self.return_splat) << shift;
+                }
+                if self.space_enable {
+                    masks.space |= eq_bits(word, self.space_splat) << shift;
+                }
+                if self.whitespace_enable {
+                    masks.whitespace |= self.whitespace_splat.iter().fold(eq_bits(word, self.space_splat), |mask, &splat| mask | eq_bits(word, splat)) << shift;
+                }
+                if self.escape_enable {
+                    masks.escape |= eq_bits(word, self.escape_splat) << shift;
+                }
+                if self.utf8_enable {
+                    masks.non_ascii |= high_bits(word) << shift;
+                }
             }
+            masks
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod archtests {
+    use crate::{default_dialect, Dialect, Terminator, Trim};
+    use crate::arch::{scalar, Backend, Classifier};
+
+    // every 64 byte window of the example files, plus the shorter tail of each
+    fn windows() -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            let data = std::fs::read(path).unwrap();
+            for start in (0..data.len()).step_by(61) {
+                out.push(data[start..(start + 64).min(data.len())].to_vec());
+            }
+        }
+        out.push(b"a;b|c\td e\"f'g\r\nh".to_vec());
+        out.push("x~|~y\u{a6}z~|\u{a6}".as_bytes().to_vec());
+        out.push(b"\x0B\x0C a\t\n\r\x08".to_vec());
+        out
+    }
+
+    fn supported_backends() -> Vec<Backend> {
+        Backend::PREFERENCE.into_iter().filter(|backend| backend.is_supported()).collect()
+    }
+
+    #[test]
+    fn test_backends_match_scalar() {
+        let mut lf = Dialect::new('|', '`', false, false);
+        lf.terminator = Terminator::Lf;
+        let mut custom = Dialect::new('\t', '\"', true, false);
+        custom.terminator = Terminator::Byte(b';');
+        custom.escapechar = Some('\\');
+        let dialects = [
+            default_dialect(),
+            Dialect::new(';', '\'', true, false),
+            lf,
+            custom,
+            Dialect::new("~|~", '\"', false, false),
+            Dialect::new('\u{a6}', '\"', false, false),
+            Dialect::builder().trim(Trim::All).build(),
+            Dialect::builder().validate_utf8(true).build(),
+        ];
+        for dialect in dialects.iter() {
+            let ours = Classifier::with_backend(dialect, Backend::Scalar);
+            for backend in supported_backends() {
+                let theirs = Classifier::with_backend(dialect, backend);
+                for window in windows().iter() {
+                    let mut padded = window.clone();
+                    padded.resize(64, 0);
+                    let padded = padded.as_slice().try_into().unwrap();
+                    let (a, b) = (ours.classify(padded), theirs.classify(padded));
+                    let valid = if window.len() == 64 { !0 } else { (1u64 << window.len()) - 1 };
+                    assert_eq!(a.delimiter & valid, b.delimiter & valid, "{:?}", backend);
+                    assert_eq!(a.delimiter_rest.map(|m| m & valid), b.delimiter_rest.map(|m| m & valid), "{:?}", backend);
+                    assert_eq!(a.quote & valid, b.quote & valid, "{:?}", backend);
+                    assert_eq!(a.newline & valid, b.newline & valid, "{:?}", backend);
+                    assert_eq!(a.carriage_return & valid, b.carriage_return & valid, "{:?}", backend);
+                    assert_eq!(a.space & valid, b.space & valid, "{:?}", backend);
+                    assert_eq!(a.whitespace & valid, b.whitespace & valid, "{:?}", backend);
+                    assert_eq!(a.escape & valid, b.escape & valid, "{:?}", backend);
+                    assert_eq!(a.non_ascii & valid, b.non_ascii & valid, "{:?}", backend);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_either() {
+        let haystack = b"0123456789abcdef,ghijklmnop\rq";
+        for end in 0..haystack.len() {
+            let expected = haystack[..end].iter().position(|&b| b == b',' || b == b'\r');
+            assert_eq!(scalar::find_either(&haystack[..end], b',', b'\r'), expected);
+        }
+        assert_eq!(scalar::find_either(haystack, b'q', b'q'), Some(28));
+    }
+
+    #[test]
+    fn test_detect() {
+        let backend = Backend::detect();
+        assert!(backend.is_supported());
+        assert_eq!(Classifier::with_backend(&default_dialect(), backend).backend(), backend);
+        assert!(Backend::Scalar.is_supported());
+    }
+
+    #[test]
+    fn test_scalar_clmul() {
+        let masks = [0u64, 1, 0x8000_0000_0000_0001, 0x0F0F_0000_1234_8001, !0, 0xdead_beef_cafe_f00d];
+        for backend in supported_backends() {
+            let classifier = Classifier::with_backend(&default_dialect(), backend);
+            for &a in masks.iter() {
+                for &b in masks.iter() {
+                    assert_eq!(scalar::clmul64(a, b), classifier.clmul64(a, b));
+                }
+            }
+        }
+        // against the definition of a prefix xor
+        let mut expected = 0u64;
+        let mut inside = false;
+        for i in 0..64 {
+            inside ^= (0x0F0F_0000_1234_8001u64 >> i) & 1 == 1;
+            expected |= (inside as u64) << i;
+        }
+        assert_eq!(scalar::clmul64(!0, 0x0F0F_0000_1234_8001), expected);
+    }
+}
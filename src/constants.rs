@@ -1,2 +1,2 @@
-
-pub(crate) const CHUNK_SIZE: usize = 64;
\ No newline at end of file
+pub(crate) const CHUNK_SIZE: usize = 64;
+pub(crate) const MAX_DELIMITER_LEN: usize = 4;
@@ -0,0 +1,315 @@
+use crate::constants::MAX_DELIMITER_LEN;
+
+/// What ends a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    /// `\r\n`, `\n` or a lone `\r`.
+    Any,
+    /// Only `\n`, a `\r` is part of the data.
+    Lf,
+    /// Only `\r\n`, a lone `\r` or `\n` is part of the data.
+    Crlf,
+    /// A single custom byte, which must be ASCII.
+    Byte(u8),
+}
+
+/// How quotes in the input are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Fields may be enclosed in `quotechar`, which hides delimiters and terminators inside them.
+    Necessary,
+    /// `quotechar` has no special meaning and every byte is taken literally. Meant for input known
+    /// to contain no quotes, which then never needs the quote bookkeeping.
+    Never,
+}
+
+/// Which records have the ASCII whitespace around their fields trimmed, as
+/// `u8::is_ascii_whitespace` defines it. The bytes that delimit or end a record are never trimmed.
+/// The header is the first record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trim {
+    None,
+    Headers,
+    Fields,
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// One to four bytes, e.g. `,`, `||` or `¦`.
+    pub delimiter: String,
+    pub quotechar: char,
+    pub quoting: QuoteStyle,
+    pub skipinitialspace: bool,
+    /// Makes the byte following it literal, e.g. `\\` for `a\,b` in MySQL dumps.
+    pub escapechar: Option<char>,
+    /// Reject malformed quoting through `Parser::read_record` instead of splitting it leniently.
+    pub strict: bool,
+    pub terminator: Terminator,
+    /// Records starting with this byte are skipped, up to the end of their line.
+    pub comment: Option<u8>,
+    /// Number of lines to skip at the start of the input, whatever they hold.
+    pub skip_lines: usize,
+    /// Skip lines until the first one with this many fields, e.g. the title rows some reporting
+    /// tools put above the header. Each line is split on its own, so a stray quote in a title
+    /// doesn't run on into the lines after it, but the header can't have a newline in a field.
+    pub skip_until_fields: Option<usize>,
+    /// Drop lines with nothing on them, instead of returning them as a record with one empty field.
+    pub skip_blank_lines: bool,
+    /// Let records have a different number of fields than the first one. Otherwise such a record
+    /// is an `ErrorKind::UnequalLengths` error.
+    pub flexible: bool,
+    /// Fields written exactly like one of these, e.g. `\N` or `NULL`, are missing values for
+    /// `Record::get_opt`.
+    pub null_values: Vec<String>,
+    /// Trims fields as they are split, by moving where they start and end.
+    pub trim: Trim,
+    /// Check that each record is UTF-8 as it is read, which makes an invalid one an
+    /// `ErrorKind::InvalidUtf8` error and lets `Record::get_str` skip the check.
+    pub validate_utf8: bool,
+}
+
+pub fn default_dialect() -> Dialect {
+    Dialect::new(
+        ',',
+        '\"',
+        false,
+        false,
+    )
+}
+
+impl Dialect {
+    /// Starts from `default_dialect()`, with each setting changeable by name.
+    pub fn builder() -> DialectBuilder {
+        DialectBuilder { dialect: default_dialect() }
+    }
+
+    /// What Excel writes, and Python's `csv.excel`.
+    pub fn excel() -> Self {
+        default_dialect()
+    }
+
+    /// Excel's tab separated variant, Python's `csv.excel_tab`.
+    pub fn excel_tab() -> Self {
+        Self::builder().delimiter(b'\t').build()
+    }
+
+    /// Python's `csv.unix_dialect`, where only `\n` ends a record.
+    pub fn unix() -> Self {
+        Self::builder().terminator(Terminator::Lf).build()
+    }
+
+    /// RFC 4180 to the letter: records end in `\r\n`, and malformed quoting or records with
+    /// differing numbers of fields are errors.
+    pub fn rfc4180() -> Self {
+        Self::builder().terminator(Terminator::Crlf).strict(true).flexible(false).build()
+    }
+
+    /// PostgreSQL's `COPY ... WITH (FORMAT csv)`, which rejects malformed quoting and ragged rows,
+    /// and reads unquoted empty fields as null.
+    pub fn postgres_csv() -> Self {
+        Self::builder().strict(true).flexible(false).null_values(&[""]).build()
+    }
+
+    /// MySQL's `SELECT ... INTO OUTFILE` defaults: tab separated, unquoted, with backslash escapes
+    /// and `\N` for null.
+    pub fn mysql_dump() -> Self {
+        Self::builder()
+            .delimiter(b'\t')
+            .quoting(QuoteStyle::Never)
+            .escape(Some(b'\\'))
+            .terminator(Terminator::Lf)
+            .null_values(&["\\N"])
+            .build()
+    }
+
+    pub fn new(delimiter: impl Into<String>, quotechar: char, skipinitialspace: bool, strict: bool) -> Self {
+        Dialect {
+            delimiter: delimiter.into(),
+            quotechar,
+            quoting: QuoteStyle::Necessary,
+            skipinitialspace,
+            escapechar: None,
+            strict,
+            terminator: Terminator::Any,
+            comment: None,
+            skip_lines: 0,
+            skip_until_fields: None,
+            skip_blank_lines: false,
+            flexible: true,
+            null_values: Vec::new(),
+            trim: Trim::None,
+            validate_utf8: false,
+        }
+    }
+
+    /// The delimiter as the byte sequence the classifier matches on.
+    ///
+    /// Panics if the delimiter is empty or longer than `MAX_DELIMITER_LEN` bytes.
+    pub(crate) fn delimiter_bytes(&self) -> &[u8] {
+        let bytes = self.delimiter.as_bytes();
+        assert!((1..=MAX_DELIMITER_LEN).contains(&bytes.len()), "delimiter {:?} is not 1 to {} bytes long", self.delimiter, MAX_DELIMITER_LEN);
+        bytes
+    }
+
+    /// The quote character as a single byte, or `None` when quoting is disabled.
+    ///
+    /// Panics if the quote character is not ASCII.
+    pub(crate) fn quotechar_byte(&self) -> Option<u8> {
+        if self.quoting == QuoteStyle::Never {
+            return None
+        }
+        assert!(self.quotechar.is_ascii(), "quotechar {:?} is not a single byte", self.quotechar);
+        Some(self.quotechar as u8)
+    }
+
+    /// The escape character as a single byte, if there is one.
+    ///
+    /// Panics if the escape character is not ASCII.
+    pub(crate) fn escapechar_byte(&self) -> Option<u8> {
+        let escapechar = self.escapechar?;
+        assert!(escapechar.is_ascii(), "escapechar {:?} is not a single byte", escapechar);
+        Some(escapechar as u8)
+    }
+
+    /// Panics if two of the delimiter, quote, escape, comment and terminator share a byte, which
+    /// would then mean two things at once.
+    fn assert_distinct(&self) {
+        let terminator: &[u8] = match &self.terminator {
+            Terminator::Any | Terminator::Crlf => b"\r\n",
+            Terminator::Lf => b"\n",
+            Terminator::Byte(b) => std::slice::from_ref(b),
+        };
+        let (quote, escape) = (self.quotechar_byte(), self.escapechar_byte());
+        let settings: [(&str, &[u8]); 5] = [
+            ("delimiter", self.delimiter.as_bytes()),
+            ("quote", quote.as_slice()),
+            ("escape", escape.as_slice()),
+            ("comment", self.comment.as_slice()),
+            ("terminator", terminator),
+        ];
+        for (i, (name, bytes)) in settings.iter().enumerate() {
+            for (other, other_bytes) in &settings[i + 1..] {
+                if let Some(b) = bytes.iter().find(|b| other_bytes.contains(b)) {
+                    panic!("{} and {} both use {:#04x}", name, other, b);
+                }
+            }
+        }
+    }
+
+    /// The byte classified as a newline, and whether `\r` is classified separately.
+    ///
+    /// Panics if a custom terminator is not ASCII.
+    pub(crate) fn terminator_bytes(&self) -> (u8, bool) {
+        match self.terminator {
+            Terminator::Any | Terminator::Crlf => (b'\n', true),
+            Terminator::Lf => (b'\n', false),
+            Terminator::Byte(b) => {
+                assert!(b.is_ascii(), "terminator {:#04x} is not ASCII", b);
+                (b, false)
+            }
+        }
+    }
+}
+
+/// Builds a `Dialect` one named setting at a time, see `Dialect::builder`.
+pub struct DialectBuilder {
+    dialect: Dialect,
+}
+
+impl DialectBuilder {
+    /// Panics if `delimiter` is not ASCII, use `delimiter_str` for longer delimiters.
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        assert!(delimiter.is_ascii(), "delimiter {:#04x} is not ASCII", delimiter);
+        self.delimiter_str(&(delimiter as char).to_string())
+    }
+
+    /// A delimiter of up to `MAX_DELIMITER_LEN` bytes, e.g. `||`.
+    pub fn delimiter_str(mut self, delimiter: &str) -> Self {
+        self.dialect.delimiter = delimiter.to_string();
+        self
+    }
+
+    /// Panics if `quote` is not ASCII.
+    pub fn quote(mut self, quote: u8) -> Self {
+        assert!(quote.is_ascii(), "quote {:#04x} is not ASCII", quote);
+        self.dialect.quotechar = quote as char;
+        self
+    }
+
+    pub fn quoting(mut self, quoting: QuoteStyle) -> Self {
+        self.dialect.quoting = quoting;
+        self
+    }
+
+    pub fn skipinitialspace(mut self, skipinitialspace: bool) -> Self {
+        self.dialect.skipinitialspace = skipinitialspace;
+        self
+    }
+
+    /// Panics if `escape` is not ASCII.
+    pub fn escape(mut self, escape: Option<u8>) -> Self {
+        if let Some(escape) = escape {
+            assert!(escape.is_ascii(), "escape {:#04x} is not ASCII", escape);
+        }
+        self.dialect.escapechar = escape.map(char::from);
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.dialect.strict = strict;
+        self
+    }
+
+    pub fn terminator(mut self, terminator: Terminator) -> Self {
+        self.dialect.terminator = terminator;
+        self
+    }
+
+    pub fn comment(mut self, comment: Option<u8>) -> Self {
+        self.dialect.comment = comment;
+        self
+    }
+
+    pub fn skip_lines(mut self, skip_lines: usize) -> Self {
+        self.dialect.skip_lines = skip_lines;
+        self
+    }
+
+    pub fn skip_until_fields(mut self, fields: Option<usize>) -> Self {
+        self.dialect.skip_until_fields = fields;
+        self
+    }
+
+    pub fn skip_blank_lines(mut self, skip_blank_lines: bool) -> Self {
+        self.dialect.skip_blank_lines = skip_blank_lines;
+        self
+    }
+
+    pub fn flexible(mut self, flexible: bool) -> Self {
+        self.dialect.flexible = flexible;
+        self
+    }
+
+    pub fn null_values(mut self, null_values: &[&str]) -> Self {
+        self.dialect.null_values = null_values.iter().map(|null| null.to_string()).collect();
+        self
+    }
+
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.dialect.trim = trim;
+        self
+    }
+
+    pub fn validate_utf8(mut self, validate_utf8: bool) -> Self {
+        self.dialect.validate_utf8 = validate_utf8;
+        self
+    }
+
+    /// Panics if two of the delimiter, quote, escape, comment and terminator share a byte, e.g.
+    /// an escape that is also the quote.
+    pub fn build(self) -> Dialect {
+        self.dialect.assert_distinct();
+        self.dialect
+    }
+}
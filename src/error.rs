@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Where in the input something happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    /// Byte offset from the start of the input.
+    pub byte: u64,
+    /// Line the record starts on, starting at 1. Comment lines and line breaks inside quoted
+    /// fields are counted too, including a lone `\r` when it is a terminator.
+    pub line: u64,
+    /// Index of the record, starting at 0.
+    pub record: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The input ended while a quoted field was still open.
+    UnterminatedQuote,
+    /// A quote appeared somewhere other than the start of a field.
+    QuoteInUnquotedField,
+    /// A closing quote was followed by something other than a delimiter, newline or quote.
+    DataAfterClosingQuote,
+    /// A record has a different number of fields than the first one, and the dialect isn't flexible.
+    UnequalLengths { expected: usize, got: usize },
+    /// A record is not valid UTF-8, and the dialect validates it. The position is that of the
+    /// first byte that isn't.
+    InvalidUtf8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    position: Position,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind, position: Position) -> Self {
+        Error { kind, position }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The offending byte, and the record it belongs to.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnterminatedQuote => write!(f, "unterminated quoted field"),
+            ErrorKind::QuoteInUnquotedField => write!(f, "quote in unquoted field"),
+            ErrorKind::DataAfterClosingQuote => write!(f, "delimiter expected after closing quote"),
+            ErrorKind::UnequalLengths { expected, got } => write!(f, "expected {} fields but found {}", expected, got),
+            ErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (record {}, line {}, byte {})", self.kind, self.position.record, self.position.line, self.position.byte)
+    }
+}
+
+impl std::error::Error for Error {}
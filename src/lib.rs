@@ -4,127 +4,734 @@ mod tests;
 mod arch;
 pub mod aligned_buffer;
 mod constants;
+mod dialect;
+mod error;
 mod record;
+mod sniff;
 
+pub use crate::dialect::{default_dialect, Dialect, DialectBuilder, QuoteStyle, Terminator, Trim};
+pub use crate::error::{Error, ErrorKind, Position};
+pub use crate::sniff::{sniff, SniffResult};
 use crate::record::Record;
+pub use crate::record::{ByteRecord, StringRecord};
 use std::ops::Index;
 use crate::aligned_buffer::AlignedBuffer;
-use crate::constants::{CHUNK_SIZE};
-use crate::arch::prefix_xor::clmul64;
-use crate::arch::simd::{Classifier};
+use crate::constants::{CHUNK_SIZE, MAX_DELIMITER_LEN};
+use crate::arch::{scalar, Classifier, Kernel};
+#[cfg(target_arch = "x86_64")]
+use crate::arch::{avx2, avx512, sse};
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+use crate::arch::neon;
+pub use crate::arch::Backend;
 
 
 extern crate test;
 
-pub struct Dialect {
-    pub delimiter: char,
-    pub quotechar: char,
-    pub skipinitialspace: bool,
-    pub strict: bool,
+pub struct Parser {
+    // fixed once the parser is built, since the classifier is made for it
+    dialect: Dialect,
+    pub inside_quotes: bool,
+    pub bufreader: AlignedBuffer,
+    // where each field of the current record starts, only kept when leading spaces are skipped,
+    // since otherwise a field starts right after the delimiter that ends the one before
+    starts: Vec<usize>,
+    ends: Vec<usize>,
+    classifier: Classifier,
+    // number of records read so far, including malformed ones
+    records: u64,
+    // number of lines read so far
+    lines: u64,
+    // whether a line with `skip_until_fields` fields has been seen
+    preamble_done: bool,
+    // the first record's number of fields, which the others are held to
+    expected_fields: Option<usize>,
+    // number of records whose number of fields differed from the first's, in flexible mode
+    ragged: u64,
 }
 
-pub fn default_dialect() -> Dialect {
-    return Dialect::new(
-        ',',
-        '\"',
-        false,
-        false,
-    );
+/// State `Parser::chunk_delimiter_ends` carries from one chunk to the next.
+#[derive(Default)]
+struct DelimiterCarry {
+    // the previous chunk's mask for each byte of the delimiter
+    bytes: [u64; MAX_DELIMITER_LEN],
+    // bytes of this chunk still covered by a delimiter that ended in the previous one
+    covered: u64,
 }
 
-impl Dialect {
-    pub fn new(delimiter: char, quotechar: char, skipinitialspace: bool, strict: bool) -> Self {
-        return Dialect {
-            delimiter,
-            quotechar,
-            skipinitialspace,
-            strict,
-        }
-    }
+/// Mask of the bits strictly below `pos`, where `pos` may be `CHUNK_SIZE`.
+#[inline(always)]
+fn mask_below(pos: usize) -> u64 {
+    if pos >= CHUNK_SIZE { !0 } else { (1u64 << pos) - 1 }
 }
 
-pub struct Parser {
-    pub dialect: Dialect,
-    pub inside_quotes: bool,
-    pub bufreader: AlignedBuffer,
-    delimiters: Vec<usize>,
-    classifier: Classifier,
+/// Positions of the set bits of `bits`, lowest first.
+///
+/// The count is known up front, so `Vec::extend` reserves once for all of them instead of
+/// checking the capacity on every push.
+#[inline(always)]
+fn bit_positions(mut bits: u64) -> impl Iterator<Item = usize> {
+    (0..bits.count_ones()).map(move |_| {
+        let pos = bits.trailing_zeros() as usize;
+        bits &= bits - 1;
+        pos
+    })
 }
+
 impl Parser {
     pub fn new(dialect: Dialect, bufreader: AlignedBuffer) -> Self {
-        return Parser {
-            dialect: dialect,
+        Self::with_backend(dialect, bufreader, Backend::detect())
+    }
+
+    /// Creates a parser that always uses `backend`, instead of the fastest one available.
+    ///
+    /// Panics if the running CPU doesn't support `backend`.
+    pub fn with_backend(dialect: Dialect, bufreader: AlignedBuffer, backend: Backend) -> Self {
+        let classifier = Classifier::with_backend(&dialect, backend);
+        Parser {
+            dialect,
             inside_quotes: false,
-            bufreader: bufreader,
-            delimiters: Vec::<usize>::new(),
-            classifier: Classifier::new(),
+            bufreader,
+            starts: Vec::<usize>::new(),
+            ends: Vec::<usize>::new(),
+            classifier,
+            records: 0,
+            lines: 0,
+            preamble_done: false,
+            expected_fields: None,
+            ragged: 0,
         }
     }
 
+    /// The dialect the parser reads with.
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.classifier.backend()
+    }
+
+    /// Number of records so far whose number of fields differed from the first record's. Only
+    /// flexible dialects let such records through, others report them as errors.
+    pub fn ragged_records(&self) -> u64 {
+        self.ragged
+    }
+
     #[inline(always)]
-    fn chunk_delimiter_offsets(quote_locations: u64, newline_locations: u64, delimiter_locations:u64, inside_quotes: bool) -> (u64, u64, u32) {
+    fn chunk_delimiter_offsets(classifier: &Classifier, quote_locations: u64, newline_locations: u64, delimiter_locations:u64, inside_quotes: bool) -> (u64, u64, u32, u64) {
         let unescaped_quote_count = quote_locations.count_ones();
 
-        // xor with current inside quotes state to get correct quote mask
-        let quote_mask = quote_locations ^ inside_quotes as u64;
-        let inside_quotes = !clmul64(!0u64, quote_mask);
-        let filtered_delimiter_locations: u64 = delimiter_locations & inside_quotes;
+        let quoted = if quote_locations == 0 {
+            // the whole chunk is on one side of a quote, so there's no need for the prefix-xor
+            0u64.wrapping_sub(inside_quotes as u64)
+        } else {
+            // xor with current inside quotes state to get correct quote mask
+            let quote_mask = quote_locations ^ inside_quotes as u64;
+            classifier.clmul64(!0u64, quote_mask)
+        };
+        let outside_quotes = !quoted;
+        let filtered_delimiter_locations: u64 = delimiter_locations & outside_quotes;
+
+        let filtered_newline_locations = newline_locations & outside_quotes;
+
+        (filtered_delimiter_locations, filtered_newline_locations, unescaped_quote_count, quoted)
+    }
+
+    /// Quotes that break the quoting rules, for strict mode.
+    ///
+    /// `quoted` is the prefix-xor of the quotes, so it covers each opening quote and the bytes up
+    /// to (but excluding) its closing quote. An opening quote is only allowed at the start of a
+    /// field or right after a closing quote (a doubled quote), and a closing quote must be followed
+    /// by a separator or another quote. When fields are trimmed, `whitespace` may come in between
+    /// a closing quote and its separator. Returns the stray opening quotes, the bytes following a
+    /// closing quote that aren't allowed there, and the carry of a closing quote, or of the
+    /// whitespace after one, in the top bit.
+    #[inline(always)]
+    fn chunk_quote_errors(quotes: u64, quoted: u64, field_starts: u64, separators: u64, whitespace: u64, close_carry: u64) -> (u64, u64, u64) {
+        let opening = quotes & quoted;
+        let closing = quotes & !quoted;
+        let after_closing = (closing << 1) | close_carry;
+        let stray_opening = opening & !(field_starts | after_closing);
+        // adding a bit at the start of a run of whitespace carries it to the byte past the run
+        let (past_whitespace, overflow) = whitespace.overflowing_add(after_closing & whitespace);
+        let next = (after_closing & !whitespace) | (past_whitespace & !whitespace);
+        let junk = next & !(separators | quotes);
+        (stray_opening, junk, (closing >> (CHUNK_SIZE - 1)) | overflow as u64)
+    }
+
+    /// Bytes preceded by an odd number of escape characters, which lose any special meaning.
+    ///
+    /// This is the technique simdjson uses for backslashes: runs of escapes that start on an odd
+    /// bit are added to themselves, so the carry flips the parity of the bits after each run, and
+    /// a run whose last escape is unpaired then escapes the following byte. `prev_escaped` carries
+    /// whether the first byte of the next chunk is escaped.
+    #[inline(always)]
+    fn chunk_escaped(escapes: u64, prev_escaped: &mut u64) -> u64 {
+        const EVEN_BITS: u64 = 0x5555_5555_5555_5555;
+        // an escaped escape doesn't escape anything
+        let escapes = escapes & !*prev_escaped;
+        let follows_escape = (escapes << 1) | *prev_escaped;
+        let odd_sequence_starts = escapes & !EVEN_BITS & !follows_escape;
+        let (sequences_starting_on_even_bits, overflow) = odd_sequence_starts.overflowing_add(escapes);
+        *prev_escaped = overflow as u64;
+        let invert_mask = sequences_starting_on_even_bits << 1;
+        (EVEN_BITS ^ invert_mask) & follows_escape
+    }
+
+    /// Where a delimiter of `len` bytes ends, given where each of its bytes is.
+    ///
+    /// Byte j has to sit `len - 1 - j` bytes before the end, so each byte's mask is shifted up by
+    /// that much (pulling in the top of the previous chunk's mask) and the results are anded.
+    /// Matches closer than `len` apart overlap, like the two in `|||` with `||`, and only the
+    /// leftmost of those is kept, the same as scanning left to right would.
+    #[inline(always)]
+    fn chunk_delimiter_ends(first: u64, rest: [u64; MAX_DELIMITER_LEN - 1], len: usize, carry: &mut DelimiterCarry) -> u64 {
+        let mut bytes = [first; MAX_DELIMITER_LEN];
+        bytes[1..].copy_from_slice(&rest);
+        let mut ends = bytes[len - 1];
+        for (shift, (&byte, &prev)) in (1..len).rev().zip(bytes.iter().zip(&carry.bytes)) {
+            ends &= (byte << shift) | (prev >> (CHUNK_SIZE - shift));
+        }
+        ends &= !carry.covered;
+        // the bytes following each match that a later match must not start in, spilling into the
+        // next chunk above bit 63
+        let covers = |ends: u64| (1..len).fold(0u128, |acc, d| acc | (ends as u128) << d);
+        if ends & covers(ends) as u64 != 0 {
+            let mut candidates = ends;
+            ends = 0;
+            while candidates != 0 {
+                let bit = candidates & candidates.wrapping_neg();
+                candidates ^= bit;
+                if bit & covers(ends) as u64 == 0 {
+                    ends |= bit;
+                }
+            }
+        }
+        carry.bytes = bytes;
+        carry.covered = (covers(ends) >> CHUNK_SIZE) as u64;
+        ends
+    }
 
-        let filtered_newline_locations = newline_locations & inside_quotes;
+    /// The bytes at the top of the chunk at `off` taken by a multi-byte delimiter that ends in the
+    /// next chunk, which only shows up in that chunk's masks.
+    ///
+    /// `first` is where the delimiter's first byte is and `ends` where the delimiters ending in
+    /// this chunk end. A match that overlaps one of those is dropped, like `chunk_delimiter_ends`
+    /// does, and of two overlapping candidates the leftmost wins.
+    fn straddling_delimiter(&self, first: u64, ends: u64, off: usize) -> u64 {
+        let delimiter = self.dialect.delimiter_bytes();
+        let taken = (1..delimiter.len()).fold(ends, |acc, d| acc | ends << d);
+        let mut candidates = first & !taken & !mask_below(CHUNK_SIZE + 1 - delimiter.len());
+        while candidates != 0 {
+            let pos = candidates.trailing_zeros() as usize;
+            if delimiter.iter().enumerate().all(|(i, &b)| self.bufreader.line_byte(off + pos + i) == Some(b)) {
+                return !mask_below(pos)
+            }
+            candidates &= candidates - 1;
+        }
+        0
+    }
 
-        return (filtered_delimiter_locations, filtered_newline_locations, unescaped_quote_count)
+    /// Moves every field start past the run of skippable spaces that follows it.
+    ///
+    /// Adding the starts that land on a space to the space mask carries through each such run and
+    /// leaves a single bit on the first byte after it; starts that don't land on a space are kept
+    /// as they are. A carry out of the top bit means a run continues into the next chunk.
+    #[inline(always)]
+    fn chunk_field_starts(field_starts: u64, spaces: u64) -> (u64, bool) {
+        let (after_runs, carry) = spaces.overflowing_add(field_starts & spaces);
+        ((after_runs | field_starts) & !spaces, carry)
     }
 
     fn reset_line_state(&mut self) {
-        self.delimiters.clear();
-        self.delimiters.push(0);
+        self.starts.clear();
+        self.ends.clear();
         self.bufreader.start_line();
         self.inside_quotes = false;
     }
 
-    fn process_buffer_chunks(&mut self) -> Option<Record<'_>> {
-        self.reset_line_state();
-        let mut off = 0;
+    /// Steps over the leading lines the dialect skips, any lines starting with the comment byte,
+    /// and the lines before the first one with `skip_until_fields` fields.
+    ///
+    /// None of these are quote aware, so their end is found with a plain byte search rather than
+    /// by classifying them.
+    fn skip_raw_lines(&mut self) {
         loop {
-            // get the next chunk from the buffer, with n<=64 valid bytes
-            let (chunk, n) = self.bufreader.get_chunk();
-            if n == 0 {
+            let leading = self.lines < self.dialect.skip_lines as u64;
+            let comment = self.dialect.comment.is_some() && self.bufreader.line_byte(0) == self.dialect.comment;
+            let preamble = !self.preamble_done && self.dialect.skip_until_fields.is_some();
+            if !(leading || comment || preamble) || self.bufreader.remaining().is_empty() {
+                break
+            }
+            let (len, terminator_len) = self.raw_line_span(0);
+            // the first line with the right number of fields is the header, read as a record
+            if !(leading || comment) && Some(self.count_fields(&self.bufreader.remaining()[..len])) == self.dialect.skip_until_fields {
+                self.preamble_done = true;
                 break
             }
-            // find delimiters, quotes, newlines
-            let (delimiter_locations, quote_locations, newline_locations) = self.classifier.classify(chunk);
-            let (mut delimiter_offsets,  newline_offsets, quote_count) = Self::chunk_delimiter_offsets(quote_locations, newline_locations, delimiter_locations, self.inside_quotes);
-            let first_newline = newline_offsets.trailing_zeros() as usize;
-            // iterate over the offsets
-            while delimiter_offsets != 0 {
-                let pos = delimiter_offsets.trailing_zeros() as usize;
-                if pos >= first_newline {
+            self.bufreader.get_line_slice(len, terminator_len);
+            self.bufreader.start_line();
+            self.lines += 1;
+        }
+    }
+
+    /// The length of the current line and of its terminator, found without regard for quotes
+    /// from `from` bytes into the line on.
+    fn raw_line_span(&self, from: usize) -> (usize, usize) {
+        let line = self.bufreader.line_rest();
+        let found = match self.dialect.terminator {
+            Terminator::Any => scalar::find_either(&line[from..], b'\n', b'\r').map(|pos| from + pos),
+            Terminator::Lf => scalar::find_either(&line[from..], b'\n', b'\n').map(|pos| from + pos),
+            Terminator::Byte(b) => scalar::find_either(&line[from..], b, b).map(|pos| from + pos),
+            // like the classifier, report the \n of the first \r\n pair
+            Terminator::Crlf => {
+                let mut from = from;
+                loop {
+                    match scalar::find_either(&line[from..], b'\n', b'\n') {
+                        Some(pos) if from + pos > 0 && line[from + pos - 1] == b'\r' => break Some(from + pos),
+                        Some(pos) => from += pos + 1,
+                        None => break None,
+                    }
+                }
+            }
+        };
+        match found {
+            Some(pos) => self.terminator_span(pos),
+            None => (line.len(), 0),
+        }
+    }
+
+    /// Ends a record that breaks the quoting rules at `pos` at the next terminator, quoted or not,
+    /// since its quotes can't be trusted past that point. `off` is where the chunk with the error
+    /// starts, whose lines haven't been counted yet.
+    fn skip_malformed_record(&mut self, pos: usize, off: usize) {
+        let (len, terminator_len) = self.raw_line_span(pos);
+        let lone_returns = self.dialect.terminator == Terminator::Any;
+        let line = self.bufreader.get_line_slice(len, terminator_len);
+        let line_breaks = (off..len).filter(|&i| line[i] == b'\n' || lone_returns && line[i] == b'\r' && line.get(i + 1) != Some(&b'\n'));
+        self.lines += line_breaks.count() as u64 + (terminator_len != 0) as u64;
+        self.records += 1;
+    }
+
+    /// The number of fields in a single line of the preamble. Quotes are only followed within the
+    /// line, so a stray one in a title can't swallow the rest of the input.
+    fn count_fields(&self, line: &[u8]) -> usize {
+        let delimiter = self.dialect.delimiter_bytes();
+        let quote = self.dialect.quotechar_byte();
+        let escape = self.dialect.escapechar_byte();
+        let mut fields = 1;
+        let mut inside_quotes = false;
+        let mut i = 0;
+        while i < line.len() {
+            if Some(line[i]) == escape {
+                i += 2;
+                continue
+            }
+            if Some(line[i]) == quote {
+                inside_quotes = !inside_quotes;
+            } else if !inside_quotes && line[i..].starts_with(delimiter) {
+                fields += 1;
+                i += delimiter.len();
+                continue
+            }
+            i += 1;
+        }
+        fields
+    }
+
+    /// Whether the dialect uses none of the features that cost the chunk loop extra work: strict
+    /// quoting, trimming, skipping initial spaces, escapes, multi-byte delimiters and UTF-8
+    /// validation.
+    fn plain(&self) -> bool {
+        let dialect = &self.dialect;
+        !dialect.strict
+            && dialect.trim == Trim::None
+            && !dialect.skipinitialspace
+            && dialect.escapechar.is_none()
+            && dialect.delimiter.len() == 1
+            && !dialect.validate_utf8
+    }
+
+    fn process_buffer_chunks(&mut self) -> Result<Option<Record<'_>>, Error> {
+        if self.plain() {
+            self.parse_record_with::<true>()
+        } else {
+            self.parse_record_with::<false>()
+        }
+    }
+
+    /// Reads the next record with a copy of the loop made for the classifier's backend.
+    fn parse_record_with<const PLAIN: bool>(&mut self) -> Result<Option<Record<'_>>, Error> {
+        match self.classifier.backend() {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => self.parse_record::<avx512::Classifier, PLAIN>(),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => self.parse_record::<avx2::Classifier, PLAIN>(),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse => self.parse_record::<sse::Classifier, PLAIN>(),
+            #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+            Backend::Neon => self.parse_record::<neon::Classifier, PLAIN>(),
+            _ => self.parse_record::<scalar::Classifier, PLAIN>(),
+        }
+    }
+
+    /// Reads the next record, classifying its chunks with `K`. With `PLAIN` set the dialect is
+    /// known to be `plain`, and the code for the features it doesn't use is compiled out of the
+    /// loop.
+    ///
+    /// Each copy is kept out of line, so it gets the registers to itself.
+    #[inline(never)]
+    fn parse_record<K: Kernel, const PLAIN: bool>(&mut self) -> Result<Option<Record<'_>>, Error> {
+        // each pass reads one record, unless it turns out to be one the dialect skips
+        'record: loop {
+            self.reset_line_state();
+            // a dialect that skips no lines shouldn't pay for a call per record
+            if self.dialect.skip_lines != 0 || self.dialect.comment.is_some() || self.dialect.skip_until_fields.is_some() {
+                self.skip_raw_lines();
+            }
+            let start = Position {
+                byte: self.bufreader.line_start() as u64,
+                line: self.lines + 1,
+                record: self.records,
+            };
+            let strict = !PLAIN && self.dialect.strict;
+            let trim = !PLAIN && match self.dialect.trim {
+                Trim::None => false,
+                Trim::Headers => self.records == 0,
+                Trim::Fields => self.records != 0,
+                Trim::All => true,
+            };
+            // whether field starts have to be moved past leading spaces
+            let skip_spaces = trim || (!PLAIN && self.dialect.skipinitialspace);
+            // when trimming, just past the last byte before this chunk that isn't a space or tab
+            let mut content_end = 0;
+            // whether a byte of this record so far is >= 0x80, when validating UTF-8
+            let mut non_ascii = false;
+            let delimiter_len = if PLAIN { 1 } else { self.dialect.delimiter_bytes().len() };
+            let crlf = self.dialect.terminator == Terminator::Crlf;
+            let mut delimiter_carry = DelimiterCarry::default();
+            let mut off = 0;
+            // the record itself starts a field at the first byte of the first chunk
+            let mut start_carry = 1u64;
+            // a \r in the last byte of the previous chunk
+            let mut return_carry = 0u64;
+            // whether the first byte of this chunk is escaped
+            let mut prev_escaped = 0u64;
+            // strict mode: a closing quote in the previous chunk's last byte, and where the most
+            // recent quoted field was opened
+            let mut close_carry = 0u64;
+            let mut last_open = 0;
+            loop {
+                // get the next chunk from the buffer, with n<=64 valid bytes
+                let (chunk, n) = self.bufreader.get_chunk();
+                if n == 0 {
                     break
                 }
-                delimiter_offsets &= delimiter_offsets - 1;
-                // +1 to include the comma, otherwise the offsets become misaligned
-                self.delimiters.push(pos + off + 1);
+                // find delimiters, quotes, newlines
+                let mut masks = K::classify(&self.classifier, chunk);
+                // the last chunk of the input is short, drop whatever was classified past its end
+                let valid = mask_below(n);
+                if n != CHUNK_SIZE {
+                    masks.delimiter &= valid;
+                    masks.quote &= valid;
+                    masks.newline &= valid;
+                    masks.carriage_return &= valid;
+                    masks.space &= valid;
+                }
+                // every line break counts towards the line number, escaped or quoted ones too
+                let (newlines, carriage_returns) = (masks.newline, masks.carriage_return);
+                if !PLAIN && masks.escape | prev_escaped != 0 {
+                    let escaped = Self::chunk_escaped(masks.escape & valid, &mut prev_escaped);
+                    masks.delimiter &= !escaped;
+                    masks.quote &= !escaped;
+                    masks.newline &= !escaped;
+                    masks.carriage_return &= !escaped;
+                    masks.space &= !escaped;
+                    masks.whitespace &= !escaped;
+                }
+                // a multi-byte delimiter is tracked by its last byte, so a field starts right after it
+                let delimiters = if delimiter_len == 1 {
+                    masks.delimiter
+                } else {
+                    Self::chunk_delimiter_ends(masks.delimiter, masks.delimiter_rest.map(|m| m & valid), delimiter_len, &mut delimiter_carry)
+                };
+                // in Crlf mode a record ends on the \n of a \r\n pair, and the \r is dropped below.
+                // Otherwise \r is only classified when it ends a record on its own.
+                let terminators = if crlf {
+                    masks.newline & ((masks.carriage_return << 1) | return_carry)
+                } else {
+                    masks.newline | masks.carriage_return
+                };
+                return_carry = masks.carriage_return >> (CHUNK_SIZE - 1);
+                let record_ends = if strict || trim { self.record_ends(terminators, masks.carriage_return, off) } else { 0 };
+                // every byte of a delimiter belongs to it, so none is skipped as a leading space or
+                // trimmed, not even a space. The part of one that started in the previous chunk is
+                // behind us already, and `trimmed_end` steps back over it byte by byte.
+                let delimiter_bytes = if !skip_spaces {
+                    0
+                } else if delimiter_len == 1 {
+                    delimiters
+                } else {
+                    (1..delimiter_len).fold(delimiters, |acc, d| acc | delimiters >> d) | self.straddling_delimiter(masks.delimiter, delimiters, off)
+                };
+                // neither is the end of the record, even if it is a tab or \n
+                let whitespace = if trim { masks.whitespace & valid & !delimiter_bytes & !record_ends } else { 0 };
+                let (delimiter_offsets,  newline_offsets, quote_count, quoted) = Self::chunk_delimiter_offsets(&self.classifier, masks.quote, terminators, delimiters, self.inside_quotes);
+                let first_newline = newline_offsets.trailing_zeros() as usize;
+                if !PLAIN && self.dialect.validate_utf8 {
+                    non_ascii |= masks.non_ascii & valid & mask_below(first_newline) != 0;
+                }
+                // only the delimiters of this record count, the rest is picked up by the next read
+                let delimiter_offsets = delimiter_offsets & mask_below(first_newline);
+                // a field starts right after each delimiter, minus any leading spaces. An empty last
+                // field starts on the newline itself, so that position is kept.
+                let field_starts = (delimiter_offsets << 1) | start_carry;
+                let (mut start_offsets, run_carry) = if skip_spaces {
+                    Self::chunk_field_starts(field_starts, (masks.space | whitespace) & !delimiter_bytes)
+                } else {
+                    (field_starts, false)
+                };
+                if strict {
+                    // a closing quote only has to be followed by the first byte of a longer delimiter,
+                    // and any byte of one ends the whitespace after it
+                    let separators = masks.delimiter | delimiter_bytes | record_ends;
+                    let (stray_opening, junk, carry) = Self::chunk_quote_errors(masks.quote, quoted, start_offsets, separators, whitespace, close_carry);
+                    let in_record = mask_below(first_newline.min(n));
+                    let (stray_opening, junk) = (stray_opening & in_record, junk & in_record);
+                    if stray_opening | junk != 0 {
+                        let pos = (stray_opening | junk).trailing_zeros() as usize;
+                        let kind = if stray_opening & (1 << pos) != 0 { ErrorKind::QuoteInUnquotedField } else { ErrorKind::DataAfterClosingQuote };
+                        self.skip_malformed_record(pos + off, off);
+                        return Err(Self::error_at(kind, pos + off, start));
+                    }
+                    let opening = masks.quote & quoted & in_record;
+                    if opening != 0 {
+                        last_open = off + (CHUNK_SIZE - 1) - opening.leading_zeros() as usize;
+                    }
+                    close_carry = carry;
+                }
+                // the terminator itself is counted once the record ends, so unless a line break is
+                // quoted, escaped or a lone one in Crlf mode there is nothing to count
+                let before_end = mask_below(first_newline);
+                if (newlines | carriage_returns) & before_end != 0 {
+                    self.lines += (self.line_breaks(newlines, carriage_returns, off) & before_end).count_ones() as u64;
+                }
+                start_offsets &= mask_below(first_newline + 1);
+                start_carry = (delimiter_offsets >> (CHUNK_SIZE - 1)) | run_carry as u64;
+                if skip_spaces {
+                    self.starts.extend(bit_positions(start_offsets).map(|pos| pos + off));
+                }
+                // the field ends where its delimiter begins
+                if trim {
+                    for pos in bit_positions(delimiter_offsets) {
+                        let end = self.trimmed_end(pos + off + 1 - delimiter_len, off, whitespace, content_end);
+                        self.ends.push(end);
+                    }
+                } else {
+                    self.ends.extend(bit_positions(delimiter_offsets).map(|pos| pos + off + 1 - delimiter_len));
+                }
+                if first_newline != CHUNK_SIZE {
+                    self.lines += 1;
+                    let (len, terminator_len) = self.chunk_terminator_span(first_newline, off, newlines, carriage_returns);
+                    self.ends.push(if trim { self.trimmed_end(len, off, whitespace, content_end) } else { len });
+                    // skipped lines may be malformed, only the records that are kept get checked
+                    if self.skip_record(len) {
+                        self.bufreader.get_line_slice(len, terminator_len);
+                        continue 'record;
+                    }
+                    self.records += 1;
+                    let mut error = self.check_field_count().map(|kind| (kind, 0));
+                    // this also steps over a malformed record, so reading can resume after it
+                    let line = self.bufreader.get_line_slice(len, terminator_len);
+                    if error.is_none() && non_ascii && let Err(e) = str::from_utf8(line) {
+                        error = Some((ErrorKind::InvalidUtf8, e.valid_up_to()));
+                    }
+                    if let Some((kind, pos)) = error {
+                        return Err(Self::error_at(kind, pos, start));
+                    }
+                    return Ok(Some(Record::new(
+                        line,
+                        self.starts.as_slice(),
+                        self.ends.as_slice(),
+                        &self.dialect,
+                        &self.classifier,
+                        start,
+                        self.dialect.validate_utf8,
+                    )));
+                }
+                if quote_count % 2 != 0 {
+                    self.inside_quotes = !self.inside_quotes;
+                }
+                if trim && !whitespace & valid != 0 {
+                    content_end = off + CHUNK_SIZE - (!whitespace & valid).leading_zeros() as usize;
+                }
+                off += n;
+                self.bufreader.consume(n);
+            }
+            if off == 0 {
+                return Ok(None)
+            }
+            // the input ended without a newline, so everything since the last one is the final record.
+            // A quoted field that is still open just runs to the end, unless we're strict.
+            if skip_spaces && start_carry != 0 {
+                self.starts.push(off);
             }
-            if first_newline != CHUNK_SIZE && first_newline <= n {
-                self.delimiters.push(first_newline + off);
-                self.bufreader.consume(first_newline);
-                return Some(Record::new(
-                    self.bufreader.get_line_slice(),
-                    self.delimiters.as_slice(),
-                ));
+            self.ends.push(if trim { self.trimmed_end(off, off, 0, content_end) } else { off });
+            if self.skip_record(off) {
+                continue 'record;
             }
-            if quote_count % 2 != 0 {
-                self.inside_quotes = !self.inside_quotes;
+            self.records += 1;
+            let mut error = if strict && self.inside_quotes {
+                Some((ErrorKind::UnterminatedQuote, last_open))
+            } else {
+                self.check_field_count().map(|kind| (kind, 0))
+            };
+            let line = self.bufreader.get_final_line_slice();
+            if error.is_none() && non_ascii && let Err(e) = str::from_utf8(line) {
+                error = Some((ErrorKind::InvalidUtf8, e.valid_up_to()));
             }
-            off += n;
-            self.bufreader.consume(n);
+            if let Some((kind, pos)) = error {
+                return Err(Self::error_at(kind, pos, start));
+            }
+            return Ok(Some(Record::new(
+                line,
+                self.starts.as_slice(),
+                self.ends.as_slice(),
+                &self.dialect,
+                &self.classifier,
+                start,
+                self.dialect.validate_utf8,
+            )));
         }
-        None
     }
-    pub fn read_line(&mut self) -> Option<Record<'_>> {
-        return self.process_buffer_chunks();
+
+    /// The line breaks of the chunk at `off`: each `\n`, and under `Terminator::Any` each `\r`
+    /// that isn't the start of a `\r\n`, whose `\n` may be in the next chunk.
+    #[inline(always)]
+    fn line_breaks(&self, newlines: u64, carriage_returns: u64, off: usize) -> u64 {
+        if carriage_returns == 0 || self.dialect.terminator != Terminator::Any {
+            return newlines
+        }
+        let mut pairs = newlines >> 1;
+        if carriage_returns >> (CHUNK_SIZE - 1) != 0 && self.bufreader.line_byte(off + CHUNK_SIZE) == Some(b'\n') {
+            pairs |= 1 << (CHUNK_SIZE - 1);
+        }
+        newlines | (carriage_returns & !pairs)
     }
-}
 
+    /// The bytes of the chunk at `off` that end a record: the terminators, and in Crlf mode the
+    /// `\r` of each `\r\n`, whose `\n` may be in the next chunk. A lone `\r` or `\n` is data.
+    #[inline(always)]
+    fn record_ends(&self, terminators: u64, carriage_returns: u64, off: usize) -> u64 {
+        if self.dialect.terminator != Terminator::Crlf {
+            return terminators
+        }
+        let mut ends = terminators | (terminators >> 1);
+        if carriage_returns >> (CHUNK_SIZE - 1) != 0 && self.bufreader.line_byte(off + CHUNK_SIZE) == Some(b'\n') {
+            ends |= 1 << (CHUNK_SIZE - 1);
+        }
+        ends
+    }
+
+    /// Where the field that ends at `end` ends once its trailing whitespace is trimmed.
+    ///
+    /// `whitespace` is the mask of the chunk at `off`, and `content_end` is the end of the content
+    /// found before that chunk. A field is never trimmed to before its start.
+    #[inline(always)]
+    fn trimmed_end(&self, end: usize, off: usize, whitespace: u64, content_end: usize) -> usize {
+        let start = self.starts[self.ends.len()];
+        let content = if end >= off {
+            let before = !whitespace & mask_below(end - off);
+            if before != 0 { off + CHUNK_SIZE - before.leading_zeros() as usize } else { content_end }
+        } else {
+            // a multi-byte delimiter or \r\n that started in the previous chunk, rare enough to
+            // just step back over the bytes
+            let mut content = end;
+            while content > start && self.bufreader.line_byte(content - 1).is_some_and(|b| b.is_ascii_whitespace()) {
+                content -= 1;
+            }
+            content
+        };
+        content.max(start)
+    }
+
+    /// Whether the record just read, `len` bytes long, is a blank line that the dialect drops.
+    fn skip_record(&self, len: usize) -> bool {
+        len == 0 && self.dialect.skip_blank_lines
+    }
+
+    /// Holds the record just read to the first record's number of fields, counting it as ragged
+    /// in flexible mode and returning the error otherwise.
+    fn check_field_count(&mut self) -> Option<ErrorKind> {
+        let got = self.ends.len();
+        let expected = *self.expected_fields.get_or_insert(got);
+        if got == expected {
+            return None
+        }
+        if self.dialect.flexible {
+            self.ragged += 1;
+            return None
+        }
+        Some(ErrorKind::UnequalLengths { expected, got })
+    }
+
+    /// Where the record's data ends and how long its terminator is, given the position (relative
+    /// to the start of the record) where it was detected.
+    fn terminator_span(&self, pos: usize) -> (usize, usize) {
+        match self.dialect.terminator {
+            Terminator::Crlf => (pos - 1, 2),
+            Terminator::Any if self.bufreader.line_byte(pos) == Some(b'\r') && self.bufreader.line_byte(pos + 1) == Some(b'\n') => (pos, 2),
+            _ => (pos, 1),
+        }
+    }
 
+    /// `terminator_span` for the terminator at `pos` in the chunk at `off`, whose masks mostly tell
+    /// whether it is the `\r` of a `\r\n` without reading the input again.
+    #[inline(always)]
+    fn chunk_terminator_span(&self, pos: usize, off: usize, newlines: u64, carriage_returns: u64) -> (usize, usize) {
+        if carriage_returns >> pos & 1 == 0 || self.dialect.terminator != Terminator::Any {
+            return self.terminator_span(pos + off)
+        }
+        let pair = if pos + 1 < CHUNK_SIZE {
+            newlines >> (pos + 1) & 1 != 0
+        } else {
+            self.bufreader.line_byte(off + CHUNK_SIZE) == Some(b'\n')
+        };
+        (pos + off, 1 + pair as usize)
+    }
+
+    fn error_at(kind: ErrorKind, pos: usize, start: Position) -> Error {
+        let position = Position {
+            byte: start.byte + pos as u64,
+            ..start
+        };
+        Error::new(kind, position)
+    }
+
+    /// Reads the next record, or `None` at the end of the input.
+    ///
+    /// Malformed quoting is only detected when the dialect is strict, and invalid UTF-8 when it
+    /// validates UTF-8. The offending record is skipped, so reading can continue after an error.
+    /// A record with malformed quoting ends at the first terminator after the error, whether or
+    /// not it is quoted.
+    pub fn read_record(&mut self) -> Result<Option<Record<'_>>, Error> {
+        self.process_buffer_chunks()
+    }
 
+    /// Like `read_record`, but copies the record into `record`, reusing its allocations. Returns
+    /// `false` at the end of the input, leaving `record` as it was.
+    pub fn read_into(&mut self, record: &mut ByteRecord) -> Result<bool, Error> {
+        match self.process_buffer_chunks()? {
+            Some(read) => {
+                record.set(&read);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Like `read_record`, but stops at the first error.
+    pub fn read_line(&mut self) -> Option<Record<'_>> {
+        self.process_buffer_chunks().unwrap_or(None)
+    }
+}
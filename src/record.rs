@@ -1,46 +1,211 @@
-use crate::Index;
+use crate::arch::Classifier;
+use crate::constants::CHUNK_SIZE;
+use crate::{mask_below, Dialect, Index, Position};
+use std::borrow::Cow;
 use std::fmt;
+use std::str::Utf8Error;
 
+#[derive(Clone, Copy)]
 pub struct Record<'a> {
     data: &'a [u8],
+    // field i spans offsets[i]..ends[i]. The parser leaves `offsets` empty when each field
+    // starts right after the delimiter that ends the one before, see `start`.
     offsets: &'a [usize],
+    ends: &'a [usize],
+    // for the delimiter, the null sentinels and the quote and escape characters
+    dialect: &'a Dialect,
+    // the parser's, to find quotes and escapes in a field
+    classifier: &'a Classifier,
+    position: Position,
+    // whether the parser checked that `data` is UTF-8
+    utf8: bool,
 }
 
 impl<'a> Record<'a> {
-    pub fn new(slice: &'a [u8], offsets: &'a [usize]) -> Self {
-        return Record {
+    /// `slice` must be valid UTF-8 if `utf8` is set, see `get_str`.
+    pub(crate) fn new(slice: &'a [u8], offsets: &'a [usize], ends: &'a [usize], dialect: &'a Dialect, classifier: &'a Classifier, position: Position, utf8: bool) -> Self {
+        Record {
             data: slice,
-            offsets: offsets,
+            offsets,
+            ends,
+            dialect,
+            classifier,
+            position,
+            utf8,
         }
     }
 
+    /// Where the record starts in the input.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
     pub fn len(&self) -> usize {
-        return self.offsets.len()-1;
+        self.ends.len()
+    }
+
+    /// Copies the record out of the parser, so it can be kept past the next read.
+    ///
+    /// Not called `to_owned`: `Record` is `Copy`, so `ToOwned::to_owned` already exists for it and
+    /// returns another `Record`, which is what a call through a reference would pick.
+    pub fn to_byte_record(self) -> ByteRecord {
+        let mut record = ByteRecord::new();
+        record.set(&self);
+        record
+    }
+
+    /// The whole record as read, without its terminator.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Where field `i` starts in `data`.
+    #[inline]
+    fn start(&self, i: usize) -> usize {
+        match i {
+            _ if !self.offsets.is_empty() => self.offsets[i],
+            0 => 0,
+            _ => self.ends[i - 1] + self.dialect.delimiter.len(),
+        }
+    }
+
+    /// The raw bytes of field `i`. Panics if `i` is out of bounds.
+    pub fn get_bytes(&self, i: usize) -> &'a [u8] {
+        &self.data[self.start(i)..self.ends[i]]
+    }
+
+    /// The raw text of field `i`, or an error if it is not valid UTF-8. Panics if `i` is out of
+    /// bounds.
+    ///
+    /// When the parser was created with a dialect that validates UTF-8 it has already checked the
+    /// whole record, so this doesn't check again.
+    pub fn get_str(&self, i: usize) -> Result<&'a str, Utf8Error> {
+        let field = self.get_bytes(i);
+        if self.utf8 {
+            // SAFETY: `utf8` is only set by the parser for records it checked. Fields are split on
+            // whole characters, so they're UTF-8 too.
+            return Ok(unsafe { str::from_utf8_unchecked(field) })
+        }
+        str::from_utf8(field)
+    }
+
+    /// The raw bytes of field `i`, as `Index` returns them, or `None` if `i` is out of bounds.
+    pub fn get(&self, i: usize) -> Option<&'a [u8]> {
+        if i >= self.len() {
+            return None
+        }
+        Some(self.get_bytes(i))
+    }
+
+    /// The raw bytes of field `i`, or `None` if they are one of the dialect's null sentinels.
+    ///
+    /// Sentinels are compared against the field as written, quotes included, so with `""` as a
+    /// sentinel an unquoted empty field is null while a quoted `""` is an empty string, the way
+    /// Postgres reads `COPY` CSV. Panics if `i` is out of bounds.
+    pub fn get_opt(&self, i: usize) -> Option<&'a [u8]> {
+        let field = self.get_bytes(i);
+        if self.dialect.null_values.iter().any(|null| null.as_bytes() == field) {
+            return None
+        }
+        Some(field)
+    }
+
+    /// The value of field `i`: without its enclosing quotes, with doubled quotes inside them
+    /// collapsed to one and with escape characters removed.
+    ///
+    /// Borrows from the input unless there was something to collapse or remove. The field is
+    /// searched for quotes and escapes with the parser's classifier, a chunk at a time, so this
+    /// costs little more than `Index` when there are none. Panics if `i` is out of bounds.
+    pub fn unescaped(&self, i: usize) -> Cow<'a, [u8]> {
+        let field = self.get_bytes(i);
+        let (inner, quoted) = match self.dialect.quotechar_byte() {
+            Some(q) if field.len() >= 2 && field[0] == q && field[field.len() - 1] == q => (&field[1..field.len() - 1], true),
+            // a quote in an unquoted field is just a quote
+            _ => (field, false),
+        };
+        if !quoted && self.dialect.escapechar_byte().is_none() {
+            return Cow::Borrowed(inner)
+        }
+        let Some(mut pos) = self.find_quote_or_escape(inner, quoted) else {
+            return Cow::Borrowed(inner)
+        };
+        let mut value = Vec::with_capacity(inner.len());
+        let mut rest = inner;
+        loop {
+            // drop the first quote of a pair, or the escape, and keep the byte after it as is
+            value.extend_from_slice(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let Some((&kept, after)) = rest.split_first() else {
+                break
+            };
+            value.push(kept);
+            rest = after;
+            match self.find_quote_or_escape(rest, quoted) {
+                Some(next) => pos = next,
+                None => {
+                    value.extend_from_slice(rest);
+                    break
+                }
+            }
+        }
+        Cow::Owned(value)
+    }
+
+    /// Position of the first escape in `bytes`, or quote if `quoted`.
+    fn find_quote_or_escape(&self, bytes: &[u8], quoted: bool) -> Option<usize> {
+        for (i, chunk) in bytes.chunks(CHUNK_SIZE).enumerate() {
+            let masks = match chunk.try_into() {
+                Ok(full) => self.classifier.classify(full),
+                Err(_) => {
+                    let mut padded = [0u8; CHUNK_SIZE];
+                    padded[..chunk.len()].copy_from_slice(chunk);
+                    self.classifier.classify(&padded)
+                }
+            };
+            let found = (masks.escape | if quoted { masks.quote } else { 0 }) & mask_below(chunk.len());
+            if found != 0 {
+                return Some(i * CHUNK_SIZE + found.trailing_zeros() as usize)
+            }
+        }
+        None
     }
 
-    pub fn iter(&'a mut self) -> RecordIterator<'a> {
-        return RecordIterator::new(self);
+    /// Like `unescaped`, but as a string, or an error if the field is not valid UTF-8.
+    pub fn unescaped_str(&self, i: usize) -> Result<Cow<'a, str>, Utf8Error> {
+        match self.unescaped(i) {
+            Cow::Borrowed(bytes) => str::from_utf8(bytes).map(Cow::Borrowed),
+            Cow::Owned(bytes) => String::from_utf8(bytes).map(Cow::Owned).map_err(|e| e.utf8_error()),
+        }
+    }
+
+    /// Iterates over the unescaped values of the fields, see `unescaped`.
+    pub fn iter_unescaped(&self) -> UnescapedIterator<'a> {
+        UnescapedIterator { record: *self, current_field: 0 }
+    }
+
+    /// Iterates over the raw bytes of the fields, the same spans `get` returns.
+    pub fn iter(&self) -> RecordIterator<'a> {
+        RecordIterator::new(self)
     }
 }
-impl<'a> fmt::Debug for Record<'_> {
+impl fmt::Debug for Record<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for i in 0..self.len() {
             if i != 0 {
                 write!(f, ", ")?;
             }
-            write!(f, "\"{}\"", &self[i])?;
+            // invalid UTF-8 shows up as U+FFFD rather than panicking
+            write!(f, "\"{}\"", String::from_utf8_lossy(self.get_bytes(i)))?;
         }
         Ok(())
     }
 }
+
+/// Panics if the field is not valid UTF-8, use `get_str` or `get_bytes` for input that may not be.
 impl<'a> Index<usize> for Record<'a> {
     type Output = str;
     fn index(&self, index: usize) -> &Self::Output {
-        let (start, mut end) = (self.offsets[index], self.offsets[index+1]);
-        if index < self.len() - 1 {
-            end -= 1;
-        }
-        return str::from_utf8(&self.data[start..end]).unwrap();
+        self.get_str(index).expect("field is not valid UTF-8")
     }
 }
 
@@ -49,25 +214,34 @@ impl<'a> PartialEq<Vec<&str>> for Record<'a> {
         if self.len() != other.len() {
             return false
         }
-        for i in 0..self.len() {
-            if &self[i] != other[i] {
+        for (field, expected) in self.iter().zip(other) {
+            if field != expected.as_bytes() {
                 return false
             }
         }
-        return true
+        true
     }
 }
 
 pub struct RecordIterator<'a> {
-    record: &'a Record<'a>,
-    current_field: usize,
+    record: Record<'a>,
+    // the fields not yet yielded from either end are front..back
+    front: usize,
+    back: usize,
+    // where the field at `front` starts when the record has no offsets, which saves looking up
+    // the end of the one before it
+    front_start: usize,
+    delimiter_len: usize,
 }
 
 impl<'a> RecordIterator<'a> {
-    pub fn new(record: &'a Record<'a>) -> RecordIterator<'a> {
-        return RecordIterator {
-            record: record,
-            current_field: 0,
+    pub fn new(record: &Record<'a>) -> RecordIterator<'a> {
+        RecordIterator {
+            record: *record,
+            front: 0,
+            back: record.len(),
+            front_start: 0,
+            delimiter_len: record.dialect.delimiter.len(),
         }
     }
 }
@@ -75,12 +249,193 @@ impl<'a> RecordIterator<'a> {
 impl<'a> Iterator for RecordIterator<'a> {
     type Item = &'a [u8];
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_field >= self.record.len() - 1 {
+        if self.front == self.back {
             return None
         }
-        let index = self.current_field;
-        let (start, end) = (self.record.offsets[index], self.record.offsets[index + 1]);
-        self.current_field += 1;
+        let i = self.front;
+        self.front += 1;
+        let end = self.record.ends[i];
+        let start = if self.record.offsets.is_empty() { self.front_start } else { self.record.offsets[i] };
+        self.front_start = end + self.delimiter_len;
         Some(&self.record.data[start..end])
     }
-}
\ No newline at end of file
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+
+impl<'a> DoubleEndedIterator for RecordIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None
+        }
+        self.back -= 1;
+        self.record.get(self.back)
+    }
+}
+
+impl<'a> ExactSizeIterator for RecordIterator<'a> {}
+
+pub struct UnescapedIterator<'a> {
+    record: Record<'a>,
+    current_field: usize,
+}
+
+impl<'a> Iterator for UnescapedIterator<'a> {
+    type Item = Cow<'a, [u8]>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_field >= self.record.len() {
+            return None
+        }
+        self.current_field += 1;
+        Some(self.record.unescaped(self.current_field - 1))
+    }
+}
+
+/// A record that owns its bytes, e.g. to collect rows or send them to another thread.
+///
+/// The record is kept as read, in one buffer, along with where each field starts and ends in it.
+/// `Parser::read_into` reuses both from one record to the next.
+///
+/// Records are equal when their fields are, wherever they were read from.
+#[derive(Clone, Default, Eq)]
+pub struct ByteRecord {
+    data: Vec<u8>,
+    // field i spans starts[i]..ends[i]
+    starts: Vec<usize>,
+    ends: Vec<usize>,
+    position: Position,
+}
+
+impl ByteRecord {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the contents with a copy of `record`, keeping the allocations.
+    pub(crate) fn set(&mut self, record: &Record<'_>) {
+        self.data.clear();
+        self.data.extend_from_slice(record.data);
+        self.starts.clear();
+        self.starts.extend((0..record.len()).map(|i| record.start(i)));
+        self.ends.clear();
+        self.ends.extend_from_slice(record.ends);
+        self.position = record.position;
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// The whole record as read, without its terminator.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Where the record started in the input it was read from.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// The raw bytes of field `i`, or `None` if `i` is out of bounds.
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        Some(&self.data[*self.starts.get(i)?..self.ends[i]])
+    }
+
+    /// Iterates over the raw bytes of the fields.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &[u8]> + ExactSizeIterator {
+        self.starts.iter().zip(&self.ends).map(|(&start, &end)| &self.data[start..end])
+    }
+}
+
+impl PartialEq for ByteRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Index<usize> for ByteRecord {
+    type Output = [u8];
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.data[self.starts[index]..self.ends[index]]
+    }
+}
+
+impl fmt::Debug for ByteRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter().map(String::from_utf8_lossy)).finish()
+    }
+}
+
+/// A `ByteRecord` that is known to be valid UTF-8. Equal like a `ByteRecord`, field by field.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct StringRecord(ByteRecord);
+
+impl StringRecord {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the whole record at once, or returns where it stops being UTF-8.
+    pub fn from_byte_record(record: ByteRecord) -> Result<Self, Utf8Error> {
+        str::from_utf8(&record.data)?;
+        Ok(StringRecord(record))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The whole record as read, without its terminator.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: checked in `from_byte_record`
+        unsafe { str::from_utf8_unchecked(&self.0.data) }
+    }
+
+    /// The raw text of field `i`, or `None` if `i` is out of bounds.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        // fields are split on whole characters, so this never panics
+        Some(&self.as_str()[*self.0.starts.get(i)?..self.0.ends[i]])
+    }
+
+    /// Iterates over the raw text of the fields.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &str> + ExactSizeIterator {
+        let text = self.as_str();
+        self.0.starts.iter().zip(&self.0.ends).map(move |(&start, &end)| &text[start..end])
+    }
+
+    /// Where the record started in the input it was read from.
+    pub fn position(&self) -> Position {
+        self.0.position
+    }
+
+    pub fn as_byte_record(&self) -> &ByteRecord {
+        &self.0
+    }
+
+    pub fn into_byte_record(self) -> ByteRecord {
+        self.0
+    }
+}
+
+impl Index<usize> for StringRecord {
+    type Output = str;
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.as_str()[self.0.starts[index]..self.0.ends[index]]
+    }
+}
+
+impl fmt::Debug for StringRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
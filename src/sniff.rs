@@ -0,0 +1,172 @@
+use crate::aligned_buffer::AlignedBuffer;
+use crate::arch::{Backend, Classifier};
+use crate::constants::CHUNK_SIZE;
+use crate::{default_dialect, mask_below, Dialect, Terminator};
+
+/// Delimiters `sniff` tries, the more common first, which also wins ties.
+const DELIMITERS: [u8; 5] = *b",\t;|:";
+const QUOTES: [u8; 2] = *b"\"'";
+/// Number of rows after the first that `sniff` looks at to decide if the first is a header.
+const HEADER_SAMPLE_ROWS: usize = 20;
+
+/// What `sniff` found out about a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SniffResult {
+    /// A dialect for the file, ready to be passed to `Parser::new`.
+    pub dialect: Dialect,
+    /// Whether the first row looks like column names rather than data.
+    pub has_header: bool,
+    /// Share of the sampled rows with the most common number of fields, from 0 to 1. It is 0 when
+    /// no candidate delimiter splits the rows into more than one field.
+    pub confidence: f64,
+}
+
+/// Guesses the dialect of the input from its first `sample_bytes` bytes, starting at the
+/// buffer's current position.
+///
+/// Each candidate delimiter and quote character is run through the SIMD classifier, the
+/// delimiters outside quotes are counted per row, and the pair whose rows most consistently have
+/// the same number of fields wins. Ties go to the pair whose quote character encloses the most
+/// fields, and then to the more common characters.
+pub fn sniff(buffer: &AlignedBuffer, sample_bytes: usize) -> SniffResult {
+    let rest = buffer.remaining();
+    let sample = &rest[..sample_bytes.min(rest.len())];
+    // a row cut off by the end of the sample would skew the field counts
+    let complete = sample.len() == rest.len();
+    let backend = Backend::detect();
+
+    let mut best: Option<(f64, usize, u8, u8)> = None;
+    for &delimiter in DELIMITERS.iter() {
+        for &quote in QUOTES.iter() {
+            let dialect = candidate(delimiter, quote);
+            let rows = split_rows(sample, complete, &Classifier::with_backend(&dialect, backend));
+            let consistency = consistency(&rows);
+            let quoted_fields = rows.iter().flatten().filter(|field| unquote(field, quote).is_some()).count();
+            if best.is_none_or(|(c, q, _, _)| (consistency, quoted_fields) > (c, q)) {
+                best = Some((consistency, quoted_fields, delimiter, quote));
+            }
+        }
+    }
+    let Some((confidence, _, delimiter, quote)) = best.filter(|&(consistency, ..)| consistency > 0.0) else {
+        return SniffResult { dialect: default_dialect(), has_header: false, confidence: 0.0 }
+    };
+
+    let mut dialect = candidate(delimiter, quote);
+    let rows = split_rows(sample, complete, &Classifier::with_backend(&dialect, backend));
+    // only skip spaces if every delimiter is followed by one
+    dialect.skipinitialspace = rows.iter().flat_map(|row| &row[1..]).all(|field| field.first() == Some(&b' '))
+        && rows.iter().any(|row| row.len() > 1);
+    dialect.terminator = terminator(sample);
+    SniffResult { has_header: has_header(&rows, quote), dialect, confidence }
+}
+
+fn candidate(delimiter: u8, quote: u8) -> Dialect {
+    Dialect::builder().delimiter(delimiter).quote(quote).build()
+}
+
+/// Splits the sample into rows of fields, skipping blank lines.
+///
+/// Any of `\r\n`, `\n` and `\r` end a row, so this works before the terminator is known. When the
+/// sample isn't the whole input its last, partial, row is dropped.
+fn split_rows<'a>(sample: &'a [u8], complete: bool, classifier: &Classifier) -> Vec<Vec<&'a [u8]>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field_start = 0;
+    let mut inside_quotes = false;
+    for (i, bytes) in sample.chunks(CHUNK_SIZE).enumerate() {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        chunk[..bytes.len()].copy_from_slice(bytes);
+        let masks = classifier.classify(&chunk);
+        let valid = mask_below(bytes.len());
+        let quotes = masks.quote & valid;
+        let quoted = classifier.clmul64(!0u64, quotes ^ inside_quotes as u64);
+        inside_quotes ^= quotes.count_ones() % 2 == 1;
+        let newlines = (masks.newline | masks.carriage_return) & valid & !quoted;
+        let mut separators = (masks.delimiter & valid & !quoted) | newlines;
+        while separators != 0 {
+            let pos = separators.trailing_zeros() as usize;
+            separators &= separators - 1;
+            let end = i * CHUNK_SIZE + pos;
+            fields.push(&sample[field_start..end]);
+            field_start = end + 1;
+            if newlines & (1 << pos) != 0 {
+                end_row(&mut rows, &mut fields);
+            }
+        }
+    }
+    if complete && field_start < sample.len() {
+        fields.push(&sample[field_start..]);
+        end_row(&mut rows, &mut fields);
+    }
+    rows
+}
+
+fn end_row<'a>(rows: &mut Vec<Vec<&'a [u8]>>, fields: &mut Vec<&'a [u8]>) {
+    // the \n of a \r\n shows up as a blank line
+    if fields.len() == 1 && fields[0].is_empty() {
+        fields.clear();
+    } else {
+        rows.push(std::mem::take(fields));
+    }
+}
+
+/// Share of the rows with the most common number of fields, or 0 if that number is 1.
+fn consistency(rows: &[Vec<&[u8]>]) -> f64 {
+    let mut counts = std::collections::HashMap::new();
+    for row in rows.iter() {
+        *counts.entry(row.len()).or_insert(0usize) += 1;
+    }
+    match counts.into_iter().max_by_key(|&(len, count)| (count, len)) {
+        Some((len, count)) if len > 1 => count as f64 / rows.len() as f64,
+        _ => 0.0,
+    }
+}
+
+/// The terminator used throughout the sample, or `Terminator::Any` if it mixes them.
+fn terminator(sample: &[u8]) -> Terminator {
+    let crlf = sample.windows(2).filter(|pair| pair == b"\r\n").count();
+    let lf = sample.iter().filter(|&&b| b == b'\n').count() - crlf;
+    let cr = sample.iter().filter(|&&b| b == b'\r').count() - crlf;
+    match (crlf, lf, cr) {
+        (1.., 0, 0) => Terminator::Crlf,
+        (0, 1.., 0) => Terminator::Lf,
+        (0, 0, 1..) => Terminator::Byte(b'\r'),
+        _ => Terminator::Any,
+    }
+}
+
+/// Guesses whether the first row holds column names, the way Python's `csv.Sniffer` does.
+///
+/// Each column whose values in the following rows are all numbers, or all of one length, votes for
+/// a header if the first row's value doesn't fit in, and against it if it does.
+fn has_header(rows: &[Vec<&[u8]>], quote: u8) -> bool {
+    let Some((header, rest)) = rows.split_first() else {
+        return false
+    };
+    let body: Vec<&Vec<&[u8]>> = rest.iter().filter(|row| row.len() == header.len()).take(HEADER_SAMPLE_ROWS).collect();
+    if body.is_empty() {
+        return false
+    }
+    let is_number = |field: &[u8]| std::str::from_utf8(value(field, quote)).is_ok_and(|s| s.trim().parse::<f64>().is_ok());
+    let mut votes = 0i32;
+    for (column, name) in header.iter().enumerate() {
+        let lengths: Vec<usize> = body.iter().map(|row| value(row[column], quote).len()).collect();
+        if body.iter().all(|row| is_number(row[column])) {
+            votes += if is_number(name) { -1 } else { 1 };
+        } else if lengths.iter().all(|&len| len == lengths[0]) {
+            votes += if value(name, quote).len() == lengths[0] { -1 } else { 1 };
+        }
+    }
+    votes > 0
+}
+
+/// A field without its quotes, if it has any.
+fn value(field: &[u8], quote: u8) -> &[u8] {
+    unquote(field, quote).unwrap_or(field)
+}
+
+/// The inside of a field enclosed in `quote`, or `None` if it isn't.
+fn unquote(field: &[u8], quote: u8) -> Option<&[u8]> {
+    let field = field.strip_prefix(b" ").unwrap_or(field);
+    field.strip_prefix(&[quote])?.strip_suffix(&[quote])
+}
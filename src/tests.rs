@@ -1,15 +1,24 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use crate::default_dialect;
-    use crate::Parser;
+    use crate::{Dialect, QuoteStyle, Terminator, Trim};
+    use crate::{ErrorKind, Position};
+    use crate::{Backend, ByteRecord, Parser, StringRecord};
+    use crate::sniff;
+    use std::borrow::Cow;
     use std::fs::File;
     use std::io::{Write};
     use crate::aligned_buffer::AlignedBuffer;
     use simd_csv::ZeroCopyReader;
 
     fn reader_from_str(s: &str) -> AlignedBuffer {
+        reader_from_bytes(s.as_bytes())
+    }
+
+    fn reader_from_bytes(bytes: &[u8]) -> AlignedBuffer {
         let mut f = tempfile::NamedTempFile::new().unwrap();
-        f.write_all(s.as_bytes()).unwrap();
+        f.write_all(bytes).unwrap();
         f.flush().unwrap();
         AlignedBuffer::new(&f.reopen().unwrap()).unwrap()
     }
@@ -96,11 +105,1259 @@ mod tests {
         assert_eq!(&record[record.len()-1], "2012");
     }
 
+    #[test]
+    fn test_line_parsing_semicolon() {
+        let line = "1;2,5;30;\"300; 400\";4\n";
+        let mut p = Parser::new(Dialect::new(';', '\"', false, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["1", "2,5", "30", "\"300; 400\"", "4"]);
+    }
+
+    #[test]
+    fn test_line_parsing_pipe() {
+        let line = "20120905_DAL@NYG|1||0|DAL,NYG|\"a|b\"|2012\nx|y\n";
+        let mut p = Parser::new(Dialect::new('|', '\"', false, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["20120905_DAL@NYG", "1", "", "0", "DAL,NYG", "\"a|b\"", "2012"]);
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["x", "y"]);
+    }
+
+    #[test]
+    fn test_line_parsing_tab() {
+        let line = "12345678910\t12345678910\t12345678910\t12345678910\toffscore, blah blah\t\"a\tb\"\tseason\n";
+        let mut p = Parser::new(Dialect::new('\t', '\"', false, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.len(), 7);
+        assert_eq!(&record[4], "offscore, blah blah");
+        assert_eq!(&record[5], "\"a\tb\"");
+        assert_eq!(&record[6], "season");
+    }
+
+    #[test]
+    fn test_line_parsing_single_quote() {
+        let line = "1,'2, 3',\"4,5\",'it''s'\n";
+        let mut p = Parser::new(Dialect::new(',', '\'', false, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["1", "'2, 3'", "\"4", "5\"", "'it''s'"]);
+    }
+
+    #[test]
+    fn test_line_parsing_backtick() {
+        let line = "12345678910,12345678910,12345678910,12345678910,`offscore,\nblah`,season\n";
+        let mut p = Parser::new(Dialect::new(',', '`', false, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.len(), 6);
+        assert_eq!(&record[4], "`offscore,\nblah`");
+        assert_eq!(&record[5], "season");
+    }
+
+    #[test]
+    fn test_line_parsing_quoting_disabled() {
+        let line = "1,\"2, 3\",4\n5\n";
+        let mut dialect = default_dialect();
+        dialect.quoting = QuoteStyle::Never;
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["1", "\"2", " 3\"", "4"]);
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["5"]);
+    }
+
+    #[test]
+    fn test_quoted_field_spanning_quote_free_chunks() {
+        // the middle chunks have no quotes at all but are still inside the quoted field
+        let quoted = format!("\"{}\"", "x,\n".repeat(60));
+        let line = format!("a,{},b\nc\n", quoted);
+        let mut p = Parser::new(default_dialect(), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", quoted.as_str(), "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c"]);
+    }
+
+    #[test]
+    fn test_skipinitialspace() {
+        let line = "a, b,  \"c, d\",e ,   ,\" f\"\n";
+        let mut p = Parser::new(Dialect::new(',', '\"', true, false), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["a", "b", "\"c, d\"", "e ", "", "\" f\""]);
+    }
+
+    #[test]
+    fn test_skipinitialspace_disabled() {
+        let line = "a, b, \"c\"\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["a", " b", " \"c\""]);
+    }
+
+    #[test]
+    fn test_skipinitialspace_across_chunks() {
+        // the run of spaces after the second delimiter straddles the first chunk boundary,
+        // and the third field starts exactly on it
+        let line = format!("{},{},{}x\n{}", "a".repeat(30), " ".repeat(40), " ".repeat(20), "  y,z\n");
+        let mut p = Parser::new(Dialect::new(',', '\"', true, false), reader_from_str(&line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["a".repeat(30).as_str(), "", "x"]);
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["y", "z"]);
+
+        let line = format!("{}, {},b\n", "a".repeat(62), "c".repeat(3));
+        let mut p = Parser::new(Dialect::new(',', '\"', true, false), reader_from_str(&line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record, vec!["a".repeat(62).as_str(), "ccc", "b"]);
+    }
+
+    #[test]
+    fn test_multi_byte_delimiters() {
+        let line = "a||b||\"c||d\"||\n";
+        let mut p = Parser::new(Dialect::new("||", '\"', false, false), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b", "\"c||d\"", ""]);
+
+        let line = "1~|~2~|~~|~3|4~\n";
+        let mut p = Parser::new(Dialect::new("~|~", '\"', false, false), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["1", "2", "", "3|4~"]);
+
+        let line = "x\u{a6}y\u{a6}\u{a6}z\n";
+        let mut p = Parser::new(Dialect::new('\u{a6}', '\"', false, false), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["x", "y", "", "z"]);
+    }
+
+    #[test]
+    fn test_multi_byte_delimiters_overlapping() {
+        // matches are taken left to right, so the leftover byte belongs to the next field
+        let line = "a|||b||||c\n";
+        let mut p = Parser::new(Dialect::new("||", '\"', false, false), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "|b", "", "c"]);
+
+        let line = "1~|~|~2\n";
+        let mut p = Parser::new(Dialect::new("~|~", '\"', false, false), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["1", "|~2"]);
+    }
+
+    #[test]
+    fn test_multi_byte_delimiters_across_chunks() {
+        // a delimiter straddling the chunk boundary at every possible split, followed by one that
+        // overlaps it
+        for prefix in 60..66 {
+            let line = format!("{}~|~|~b~|~c\n", "a".repeat(prefix));
+            let mut p = Parser::new(Dialect::new("~|~", '\"', false, false), reader_from_str(&line));
+            assert_eq!(p.read_line().unwrap(), vec!["a".repeat(prefix).as_str(), "|~b", "c"], "prefix {}", prefix);
+        }
+        let line = format!("{}||||x\n", "a".repeat(63));
+        let mut p = Parser::new(Dialect::new("||", '\"', false, false), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec!["a".repeat(63).as_str(), "", "x"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_delimiter_too_long() {
+        Parser::new(Dialect::new("|||||", '\"', false, false), reader_from_str("a\n"));
+    }
+
+    fn strict_dialect() -> Dialect {
+        Dialect::new(',', '\"', false, true)
+    }
+
+    #[test]
+    fn test_strict_valid_quoting() {
+        let line = "1,\"a \"\"b\"\" c\",\"\",\"x,\ny\"\n2,3\n";
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["1", "\"a \"\"b\"\" c\"", "\"\"", "\"x,\ny\""]);
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["2", "3"]);
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strict_quote_in_unquoted_field() {
+        let line = "a,b\"c\",d\ne,f\n";
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuoteInUnquotedField);
+        assert_eq!(err.position(), Position { byte: 3, line: 1, record: 0 });
+        // the malformed record is skipped
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["e", "f"]);
+    }
+
+    #[test]
+    fn test_strict_stray_quote_recovery() {
+        // the stray quote is never closed, so the record ends at the next newline regardless
+        let line = "a,b\"c,d\ne,f\ng,h\n";
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuoteInUnquotedField);
+        assert_eq!(err.position(), Position { byte: 3, line: 1, record: 0 });
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["e", "f"]);
+        assert_eq!(record.position(), Position { byte: 8, line: 2, record: 1 });
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["g", "h"]);
+        assert!(p.read_record().unwrap().is_none());
+
+        // an error in a later chunk, after a quoted newline in the same record
+        let line = format!("\"x\ny\",{}b\"c,d\ne,f\n", "a".repeat(70));
+        let mut p = Parser::new(strict_dialect(), reader_from_str(&line));
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuoteInUnquotedField);
+        assert_eq!(err.position(), Position { byte: 77, line: 1, record: 0 });
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["e", "f"]);
+        assert_eq!(record.position(), Position { byte: 82, line: 3, record: 1 });
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strict_data_after_closing_quote() {
+        let line = "a,b\n\"c\"x,d\n";
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        p.read_record().unwrap();
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::DataAfterClosingQuote);
+        assert_eq!(err.position(), Position { byte: 7, line: 2, record: 1 });
+    }
+
+    #[test]
+    fn test_strict_doubled_quote_across_chunks() {
+        // the doubled quote straddles the chunk boundary
+        let line = format!("{},\"b\"\"{}\"\n", "a".repeat(60), "c".repeat(10));
+        let mut p = Parser::new(strict_dialect(), reader_from_str(&line));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record.len(), 2);
+    }
+
+    #[test]
+    fn test_strict_unterminated_quote() {
+        let line = "a,b\nc,\"d,\ne\n";
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        p.read_record().unwrap();
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnterminatedQuote);
+        assert_eq!(err.position(), Position { byte: 6, line: 2, record: 1 });
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strict_skipinitialspace() {
+        let line = "a, \"b, c\"\n";
+        let mut p = Parser::new(Dialect::new(',', '\"', true, true), reader_from_str(line));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["a", "\"b, c\""]);
+
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        assert_eq!(p.read_record().err().unwrap().kind(), ErrorKind::QuoteInUnquotedField);
+    }
+
+    #[test]
+    fn test_strict_crlf_closing_quote() {
+        // only a whole \r\n ends a record, a lone \n or \r is data
+        for line in ["\"a\"\nb\r\n", "\"a\"\rb\r\n"] {
+            let mut p = Parser::new(Dialect::rfc4180(), reader_from_str(line));
+            let err = p.read_record().unwrap_err();
+            assert_eq!((err.kind(), err.position().byte), (ErrorKind::DataAfterClosingQuote, 3));
+        }
+        let mut p = Parser::new(Dialect::rfc4180(), reader_from_str("\"a\"\r\n\"b\"\r\n"));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["\"a\""]);
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["\"b\""]);
+
+        // the \r is the last byte of a chunk, and its \n the first of the next
+        let field = format!("\"{}\"", "a".repeat(61));
+        let mut p = Parser::new(Dialect::rfc4180(), reader_from_str(&format!("{}\r\nb\r\n", field)));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec![field.as_str()]);
+        let mut p = Parser::new(Dialect::rfc4180(), reader_from_str(&format!("{}\rx\r\n", field)));
+        assert_eq!(p.read_record().unwrap_err().kind(), ErrorKind::DataAfterClosingQuote);
+    }
+
+    #[test]
+    fn test_lenient_accepts_malformed() {
+        let line = "a,b\"c\",d\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["a", "b\"c\"", "d"]);
+    }
+
+    #[test]
+    fn test_strict_example_files() {
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            let file = File::open(path).unwrap();
+            let mut p = Parser::new(strict_dialect(), AlignedBuffer::new(&file).unwrap());
+            while p.read_record().unwrap().is_some() {}
+        }
+    }
+
+    #[test]
+    fn test_no_trailing_newline() {
+        let line = "1,2\n3,\"4, 5\"";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["1", "2"]);
+        assert_eq!(p.read_line().unwrap(), vec!["3", "\"4, 5\""]);
+        assert!(p.read_line().is_none());
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_no_trailing_newline_empty_last_field() {
+        let mut p = Parser::new(default_dialect(), reader_from_str("a,b,"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b", ""]);
+
+        // the final delimiter is the last byte of a full chunk
+        let line = format!("{},", "a".repeat(63));
+        let mut p = Parser::new(default_dialect(), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec!["a".repeat(63).as_str(), ""]);
+
+        let mut p = Parser::new(Dialect::new(',', '\"', true, false), reader_from_str("a,   "));
+        assert_eq!(p.read_line().unwrap(), vec!["a", ""]);
+    }
+
+    #[test]
+    fn test_file_ends_on_page_boundary() {
+        // 4096 bytes, so the final partial chunk ends exactly where the mapping does
+        let line = format!("{}\n{},x", "a,".repeat(2000), "b".repeat(93));
+        assert_eq!(line.len(), 4096);
+        for backend in Backend::PREFERENCE.into_iter().filter(|b| b.is_supported()) {
+            let mut p = Parser::with_backend(default_dialect(), reader_from_str(&line), backend);
+            assert_eq!(p.read_line().unwrap().len(), 2001);
+            assert_eq!(p.read_line().unwrap(), vec!["b".repeat(93).as_str(), "x"]);
+            assert!(p.read_line().is_none());
+        }
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let mut p = Parser::new(default_dialect(), reader_from_str(""));
+        assert!(p.read_line().is_none());
+        let mut p = Parser::new(strict_dialect(), reader_from_str(""));
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ends_with_carriage_return() {
+        let mut p = Parser::new(default_dialect(), reader_from_str("a,b\r\nc,d\r"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c", "d"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_ends_inside_quotes() {
+        let line = "a,b\nc,\"d,\ne";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        p.read_line().unwrap();
+        assert_eq!(p.read_line().unwrap(), vec!["c", "\"d,\ne"]);
+        assert!(p.read_line().is_none());
+
+        let mut p = Parser::new(strict_dialect(), reader_from_str(line));
+        p.read_record().unwrap();
+        assert_eq!(p.read_record().err().unwrap().kind(), ErrorKind::UnterminatedQuote);
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    fn terminator_dialect(terminator: Terminator) -> Dialect {
+        let mut dialect = default_dialect();
+        dialect.terminator = terminator;
+        dialect
+    }
+
+    #[test]
+    fn test_terminator_any_lone_carriage_return() {
+        let line = "a,b\rc,d\r\ne\n\"f\rg\"\rh";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c", "d"]);
+        assert_eq!(p.read_line().unwrap(), vec!["e"]);
+        assert_eq!(p.read_line().unwrap(), vec!["\"f\rg\""]);
+        assert_eq!(p.read_line().unwrap(), vec!["h"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_terminator_crlf_across_chunks() {
+        // the \r is the last byte of the first chunk, the \n the first of the next
+        let line = format!("{},b\r\nc\r\n", "a".repeat(61));
+        for terminator in [Terminator::Any, Terminator::Crlf] {
+            let mut p = Parser::new(terminator_dialect(terminator), reader_from_str(&line));
+            assert_eq!(p.read_line().unwrap(), vec!["a".repeat(61).as_str(), "b"]);
+            assert_eq!(p.read_line().unwrap(), vec!["c"]);
+            assert!(p.read_line().is_none());
+        }
+    }
+
+    #[test]
+    fn test_terminator_lf() {
+        let line = "a,b\r\nc\rd\n\r\n";
+        let mut p = Parser::new(terminator_dialect(Terminator::Lf), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b\r"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c\rd"]);
+        assert_eq!(p.read_line().unwrap(), vec!["\r"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_terminator_crlf() {
+        let line = "a,b\r\nc\nd\re,\r\n\"\r\n\"\r\nf\r";
+        let mut p = Parser::new(terminator_dialect(Terminator::Crlf), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c\nd\re", ""]);
+        assert_eq!(p.read_line().unwrap(), vec!["\"\r\n\""]);
+        assert_eq!(p.read_line().unwrap(), vec!["f\r"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_terminator_custom_byte() {
+        let line = "a,b;c\nd,\"e;f\";g\r";
+        let mut p = Parser::new(terminator_dialect(Terminator::Byte(b';')), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c\nd", "\"e;f\""]);
+        assert_eq!(p.read_line().unwrap(), vec!["g\r"]);
+        assert!(p.read_line().is_none());
+    }
+
+    fn escape_dialect() -> Dialect {
+        let mut dialect = default_dialect();
+        dialect.escapechar = Some('\\');
+        dialect
+    }
+
+    #[test]
+    fn test_escapechar() {
+        let line = "1,a\\,b,\"c\\\"d, e\",f\\\\,g\\\nh\n2\n";
+        let mut p = Parser::new(escape_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["1", "a\\,b", "\"c\\\"d, e\"", "f\\\\", "g\\\nh"]);
+        assert_eq!(p.read_line().unwrap(), vec!["2"]);
+
+        // without an escape character the backslash is just data
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        assert_eq!(&p.read_line().unwrap()[1], "a\\");
+    }
+
+    #[test]
+    fn test_escapechar_across_chunks() {
+        // an escape in the last byte of a chunk escapes the first byte of the next
+        let line = format!("{}\\,b,c\n", "a".repeat(63));
+        let mut p = Parser::new(escape_dialect(), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec![format!("{}\\,b", "a".repeat(63)).as_str(), "c"]);
+
+        // an even run of escapes doesn't
+        let line = format!("{}\\\\,b,c\n", "a".repeat(62));
+        let mut p = Parser::new(escape_dialect(), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec![format!("{}\\\\", "a".repeat(62)).as_str(), "b", "c"]);
+
+        // an odd run spanning the boundary does
+        let line = format!("{}\\\\\\,b,c\n", "a".repeat(62));
+        let mut p = Parser::new(escape_dialect(), reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_escaped_bytes_match_scan() {
+        // compare the bitmask version with a byte at a time scan over a few chunks
+        let input: Vec<u8> = (0..640u32).map(|i| if (i * 7919) % 13 < 6 { b'\\' } else { b'x' }).collect();
+        let mut expected = vec![false; input.len()];
+        let mut escaping = false;
+        for (i, &b) in input.iter().enumerate() {
+            expected[i] = escaping;
+            escaping = !escaping && b == b'\\';
+        }
+        let mut prev_escaped = 0;
+        for (c, chunk) in input.chunks(64).enumerate() {
+            let escapes = chunk.iter().enumerate().fold(0u64, |m, (i, &b)| m | (((b == b'\\') as u64) << i));
+            let escaped = Parser::chunk_escaped(escapes, &mut prev_escaped);
+            for i in 0..64 {
+                assert_eq!(escaped >> i & 1 == 1, expected[c * 64 + i], "byte {}", c * 64 + i);
+            }
+        }
+    }
+
+    fn comment_dialect() -> Dialect {
+        let mut dialect = default_dialect();
+        dialect.comment = Some(b'#');
+        dialect
+    }
+
+    #[test]
+    fn test_comment_lines() {
+        let line = "# license: \"unterminated, quote\n#\na,#b\n# between\r\nc,d\n#trailing";
+        let mut p = Parser::new(comment_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "#b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c", "d"]);
+        assert!(p.read_line().is_none());
+
+        // the comment byte is data when comments are off
+        let mut p = Parser::new(default_dialect(), reader_from_str("#a,b\n"));
+        assert_eq!(p.read_line().unwrap(), vec!["#a", "b"]);
+    }
+
+    #[test]
+    fn test_comment_lines_count_toward_line_numbers() {
+        let line = "# one\n# two\na,b\n# four\nc,d\"\n";
+        let mut dialect = comment_dialect();
+        dialect.strict = true;
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        p.read_record().unwrap();
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.position(), Position { byte: 26, line: 5, record: 1 });
+    }
+
+    #[test]
+    fn test_comment_lines_crlf() {
+        let line = "#a\rb\r\nc\r\n";
+        let mut p = Parser::new(Dialect { terminator: Terminator::Crlf, ..comment_dialect() }, reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["c"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_skip_leading_lines() {
+        let line = "Quarterly \"report\nGenerated, today\na,b\n1,2\n";
+        let mut p = Parser::new(Dialect::builder().skip_lines(2).build(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["1", "2"]);
+        assert!(p.read_line().is_none());
+
+        // more lines to skip than there are
+        let mut p = Parser::new(Dialect::builder().skip_lines(10).build(), reader_from_str(line));
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_skip_until_fields() {
+        let line = "Sales report\n\nRegion: \"North\", 2024\nid,name,total\n1,x,2\n3\n";
+        let dialect = Dialect::builder().skip_until_fields(Some(3)).strict(true).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["id", "name", "total"]);
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["1", "x", "2"]);
+        // once the preamble is over, short records are kept
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["3"]);
+        assert!(p.read_record().unwrap().is_none());
+
+        // the preamble may be all there is, without a newline at the end
+        let mut p = Parser::new(Dialect::builder().skip_until_fields(Some(3)).build(), reader_from_str("a\nb,c"));
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_skip_until_fields_stray_quote() {
+        // the quote in the title doesn't carry over to the lines after it
+        let line = "Title \"x\ny,z\n1,2\n";
+        let mut p = Parser::new(Dialect::builder().skip_until_fields(Some(2)).build(), reader_from_str(line));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record, vec!["y", "z"]);
+        assert_eq!(record.position(), Position { byte: 9, line: 2, record: 0 });
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["1", "2"]);
+        assert!(p.read_record().unwrap().is_none());
+
+        // a header with quoted and escaped delimiters is still recognised
+        let line = "Report, 2024, final\n\"a,b\",c\\,d\n1,2\n";
+        let dialect = Dialect::builder().skip_until_fields(Some(2)).escape(Some(b'\\')).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["\"a,b\"", "c\\,d"]);
+    }
+
+    #[test]
+    fn test_skip_blank_lines() {
+        let line = "\na,b\n\n\r\n\rc\n\n";
+        let mut p = Parser::new(Dialect::builder().skip_blank_lines(true).build(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec!["c"]);
+        assert!(p.read_line().is_none());
+
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec![""]);
+
+        // a blank line isn't a field with only spaces in it
+        let mut p = Parser::new(Dialect::builder().skip_blank_lines(true).build(), reader_from_str(" \n"));
+        assert_eq!(p.read_line().unwrap(), vec![" "]);
+    }
+
+    #[test]
+    fn test_skipped_lines_count_toward_positions() {
+        let line = "title\n\na,b\n\nc,\"d\n";
+        let dialect = Dialect::builder().skip_lines(1).skip_blank_lines(true).strict(true).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        p.read_record().unwrap();
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.position(), Position { byte: 14, line: 5, record: 1 });
+    }
+
+    #[test]
+    fn test_fixed_field_count() {
+        let line = "a,b,c\n1,2,3\n4,5\n6,7,8,9\n10,11,12";
+        let mut p = Parser::new(Dialect::builder().flexible(false).build(), reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap().len(), 3);
+        assert_eq!(p.read_record().unwrap().unwrap().len(), 3);
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnequalLengths { expected: 3, got: 2 });
+        assert_eq!(err.position(), Position { byte: 12, line: 3, record: 2 });
+        assert_eq!(err.to_string(), "expected 3 fields but found 2 (record 2, line 3, byte 12)");
+        let err = p.read_record().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnequalLengths { expected: 3, got: 4 });
+        // the final record has no newline
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["10", "11", "12"]);
+        assert!(p.read_record().unwrap().is_none());
+        assert_eq!(p.ragged_records(), 0);
+    }
+
+    #[test]
+    fn test_fixed_field_count_final_record() {
+        let mut p = Parser::new(Dialect::builder().flexible(false).build(), reader_from_str("a,b\nc"));
+        p.read_record().unwrap();
+        assert_eq!(p.read_record().err().unwrap().kind(), ErrorKind::UnequalLengths { expected: 2, got: 1 });
+    }
+
+    #[test]
+    fn test_flexible_counts_ragged_records() {
+        let line = "a,b,c\n1,2\n3,4,5\n6\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap().len(), 3);
+        assert_eq!(p.read_line().unwrap(), vec!["1", "2"]);
+        assert_eq!(p.read_line().unwrap().len(), 3);
+        assert_eq!(p.read_line().unwrap(), vec!["6"]);
+        assert_eq!(p.ragged_records(), 2);
+    }
+
+    #[test]
+    fn test_field_count_ignores_skipped_records() {
+        // the count is locked onto the first record that is kept
+        let line = "Title\n\nx,y\n1,2\n";
+        let dialect = Dialect::builder().skip_lines(1).skip_blank_lines(true).flexible(false).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["x", "y"]);
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_null_values() {
+        let line = ",\"\",\\N,NA,NULL,\"NULL\",null,x\n";
+        let dialect = Dialect::builder().null_values(&["", "\\N", "NA", "NULL"]).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        let record = p.read_line().unwrap();
+        let fields: Vec<Option<&[u8]>> = (0..record.len()).map(|i| record.get_opt(i)).collect();
+        assert_eq!(fields, vec![None, Some(&b"\"\""[..]), None, None, None, Some(b"\"NULL\""), Some(b"null"), Some(b"x")]);
+
+        // nothing is null by default
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        assert!((0..record.len()).all(|i| record.get_opt(i).is_some()));
+    }
+
+    #[test]
+    fn test_null_values_presets() {
+        let mut p = Parser::new(Dialect::postgres_csv(), reader_from_str("1,,\"\"\n"));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!((record.get_opt(0), record.get_opt(1), record.get_opt(2)), (Some(&b"1"[..]), None, Some(&b"\"\""[..])));
+
+        let mut p = Parser::new(Dialect::mysql_dump(), reader_from_str("1\t\\N\t\n"));
+        let record = p.read_line().unwrap();
+        assert_eq!((record.get_opt(0), record.get_opt(1), record.get_opt(2)), (Some(&b"1"[..]), None, Some(&b""[..])));
+    }
+
+    #[test]
+    fn test_invalid_utf8_accessors() {
+        let mut p = Parser::new(default_dialect(), reader_from_bytes(b"caf\xe9,ok,\xc3\xa9\r\n"));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.as_bytes(), b"caf\xe9,ok,\xc3\xa9");
+        assert_eq!(record.get_bytes(0), b"caf\xe9");
+        assert_eq!(record.get_str(0).unwrap_err().valid_up_to(), 3);
+        assert_eq!((record.get_str(1), record.get_str(2)), (Ok("ok"), Ok("\u{e9}")));
+        assert_eq!(&record[1], "ok");
+        assert_eq!(format!("{:?}", record), "\"caf\u{fffd}\", \"ok\", \"\u{e9}\"");
+        assert!(record != vec!["caf", "ok", "\u{e9}"]);
+    }
+
+    #[test]
+    fn test_validate_utf8() {
+        let dialect = Dialect::builder().validate_utf8(true).build();
+        let long = "\u{e9}".repeat(40);
+        // the chunk that ends "x" carries on into the next record, which is not ASCII
+        let mut input = format!("a,\u{e9}\n{},b\nx\n{},c", long, long).into_bytes();
+        let invalid = input.len();
+        input.extend_from_slice(b"\xff,d\nafter,");
+        input.extend_from_slice("\u{e9}\n\u{e9}".as_bytes());
+        input.push(0xe9);
+        let mut p = Parser::new(dialect, reader_from_bytes(&input));
+        assert!(p.dialect().validate_utf8);
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record.get_str(1), Ok("\u{e9}"));
+        let record = p.read_record().unwrap().unwrap();
+        assert_eq!(record.get_str(0), Ok(long.as_str()));
+        assert_eq!(&record[1], "b");
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["x"]);
+
+        let error = p.read_record().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidUtf8);
+        assert_eq!(error.position(), Position { byte: invalid as u64, line: 4, record: 3 });
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["after", "\u{e9}"]);
+        // a final record without a newline is checked too
+        assert_eq!(p.read_record().unwrap_err().position().byte, (input.len() - 1) as u64);
+        assert!(p.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_record_position() {
+        let line = "h1,h2\n\"multi\nline\",x\nplain,y\r\n\"a\n\nb\",z\nlast";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let mut positions = Vec::new();
+        while let Some(record) = p.read_line() {
+            positions.push(record.position());
+        }
+        assert_eq!(positions, vec![
+            Position { byte: 0, line: 1, record: 0 },
+            Position { byte: 6, line: 2, record: 1 },
+            Position { byte: 21, line: 4, record: 2 },
+            Position { byte: 30, line: 5, record: 3 },
+            Position { byte: 39, line: 8, record: 4 },
+        ]);
+    }
+
+    #[test]
+    fn test_record_position_across_chunks() {
+        // newlines in a quoted field spanning several chunks, escaped ones, and skipped lines
+        let quoted = format!("\"{}\"", "text\n".repeat(30));
+        let line = format!("# comment\n{},1\n\n2\\\n3,4\nfifth,5\n", quoted);
+        let dialect = Dialect::builder().comment(Some(b'#')).escape(Some(b'\\')).skip_blank_lines(true).build();
+        let mut p = Parser::new(dialect, reader_from_str(&line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.position(), Position { byte: 10, line: 2, record: 0 });
+        let record = p.read_line().unwrap();
+        let byte = 10 + quoted.len() as u64 + 4;
+        assert_eq!(record.position(), Position { byte, line: 34, record: 1 });
+        let record = p.read_line().unwrap().to_byte_record();
+        assert_eq!(record.position(), Position { byte: byte + 7, line: 36, record: 2 });
+        assert!(p.read_line().is_none());
+
+        // errors agree with the records around them
+        let line = format!("{},1\nx,\"y\"z\nw\n", quoted);
+        let mut p = Parser::new(Dialect::builder().strict(true).build(), reader_from_str(&line));
+        assert_eq!(p.read_record().unwrap().unwrap().position().line, 1);
+        let err = p.read_record().unwrap_err();
+        assert_eq!(err.position(), Position { byte: quoted.len() as u64 + 8, line: 32, record: 1 });
+        assert_eq!(p.read_record().unwrap().unwrap().position(), Position { byte: quoted.len() as u64 + 10, line: 33, record: 2 });
+    }
+
+    #[test]
+    fn test_record_position_carriage_returns() {
+        // a lone \r in a quoted field is a line break like a lone \n, a \r\n pair is one
+        let line = "\"a\rb\",1\n\"c\r\nd\",2\n\"e\nf\",3\nx,4\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let mut lines = Vec::new();
+        while let Some(record) = p.read_line() {
+            lines.push(record.position().line);
+        }
+        assert_eq!(lines, vec![1, 3, 5, 7]);
+
+        // a \r in the last byte of a chunk, with and without a \n following in the next one
+        for (quoted_break, lines_after) in [("\r\n", 3), ("\r", 3), ("\r\r", 4)] {
+            let line = format!("\"{}{}x\",1\ny,2\n", "a".repeat(62), quoted_break);
+            let mut p = Parser::new(default_dialect(), reader_from_str(&line));
+            p.read_line().unwrap();
+            assert_eq!(p.read_line().unwrap().position().line, lines_after, "{quoted_break:?}");
+        }
+
+        // only \n breaks lines when a lone \r is data
+        let dialect = Dialect::builder().terminator(Terminator::Crlf).build();
+        let mut p = Parser::new(dialect, reader_from_str("\"a\rb\",1\r\nx,2\r\n"));
+        p.read_line().unwrap();
+        assert_eq!(p.read_line().unwrap().position().line, 2);
+    }
+
+    #[test]
+    fn test_record_iter() {
+        let line = "1,\"a,b\",,last\nx\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        let fields: Vec<&[u8]> = record.iter().collect();
+        assert_eq!(fields, vec![&b"1"[..], b"\"a,b\"", b"", b"last"]);
+        assert!(fields.iter().enumerate().all(|(i, &field)| field == record[i].as_bytes()));
+        assert_eq!(record.iter().len(), 4);
+        let reversed: Vec<&[u8]> = record.iter().rev().collect();
+        assert_eq!(reversed, vec![&b"last"[..], b"", b"\"a,b\"", b"1"]);
+
+        // both ends meet in the middle
+        let mut fields = record.iter();
+        assert_eq!((fields.next(), fields.next_back()), (Some(&b"1"[..]), Some(&b"last"[..])));
+        assert_eq!(fields.len(), 2);
+        assert_eq!((fields.next_back(), fields.next()), (Some(&b""[..]), Some(&b"\"a,b\""[..])));
+        assert_eq!((fields.next(), fields.next_back(), fields.len()), (None, None, 0));
+
+        assert_eq!((record.get(3), record.get(4)), (Some(&b"last"[..]), None));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec![&b"x"[..]]);
+    }
+
+    #[test]
+    fn test_record_iter_matches_index() {
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            let file = File::open(path).unwrap();
+            let mut p = Parser::new(default_dialect(), AlignedBuffer::new(&file).unwrap());
+            while let Some(record) = p.read_line() {
+                assert_eq!(record.iter().len(), record.len());
+                for (i, field) in record.iter().enumerate() {
+                    assert_eq!(field, record[i].as_bytes());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_owned_records() {
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            let file = File::open(path).unwrap();
+            let mut p = Parser::new(default_dialect(), AlignedBuffer::new(&file).unwrap());
+            let mut rows = Vec::new();
+            let mut expected = Vec::new();
+            while let Some(record) = p.read_line() {
+                expected.push(record.iter().map(|field| field.to_vec()).collect::<Vec<_>>());
+                // through a reference, the way closures and iterators see records
+                rows.extend([record].iter().map(|record| record.to_byte_record()));
+            }
+            let rows: Vec<ByteRecord> = rows;
+            assert_eq!(rows.len(), expected.len());
+            for (row, fields) in rows.iter().zip(&expected) {
+                assert_eq!(row.len(), fields.len());
+                assert!(row.iter().eq(fields.iter().map(|field| field.as_slice())));
+                assert!((0..row.len()).all(|i| &row[i] == fields[i].as_slice() && row.get(i) == Some(&fields[i][..])));
+                assert_eq!(row.get(row.len()), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_into() {
+        let line = "a,\"b,c\",dddddddddd\nx,y\n\nlast";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let mut record = ByteRecord::new();
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec![&b"a"[..], b"\"b,c\"", b"dddddddddd"]);
+        let buffer = record.as_bytes().as_ptr();
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!(record.iter().rev().collect::<Vec<_>>(), vec![&b"y"[..], b"x"]);
+        // the smaller record fits in the same buffer
+        assert_eq!(record.as_bytes().as_ptr(), buffer);
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!((record.len(), &record[0]), (1, &b""[..]));
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!(record.as_bytes(), b"last");
+        assert!(!p.read_into(&mut record).unwrap());
+        assert_eq!(record.as_bytes(), b"last");
+
+        let mut p = Parser::new(Dialect::rfc4180(), reader_from_str("a,b\r\nc\r\nd,e\r\n"));
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!(p.read_into(&mut record).unwrap_err().kind(), ErrorKind::UnequalLengths { expected: 2, got: 1 });
+        assert!(p.read_into(&mut record).unwrap());
+        assert_eq!(format!("{:?}", record), "[\"d\", \"e\"]");
+    }
+
+    #[test]
+    fn test_string_record() {
+        let mut p = Parser::new(default_dialect(), reader_from_bytes(b"caf\xc3\xa9,\"x\"\nbad\xff,y\n"));
+        let record = StringRecord::from_byte_record(p.read_line().unwrap().to_byte_record()).unwrap();
+        assert_eq!((record.len(), &record[0], record.get(1), record.get(2)), (2, "caf\u{e9}", Some("\"x\""), None));
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!["caf\u{e9}", "\"x\""]);
+        assert_eq!(record.as_str(), "caf\u{e9},\"x\"");
+        assert_eq!(format!("{:?}", record), "[\"caf\u{e9}\", \"\\\"x\\\"\"]");
+        // owned records can leave the parser's thread
+        let handle = std::thread::spawn(move || record.into_byte_record());
+        assert_eq!(handle.join().unwrap().as_bytes(), "caf\u{e9},\"x\"".as_bytes());
+
+        let bytes = p.read_line().unwrap().to_byte_record();
+        assert_eq!(StringRecord::from_byte_record(bytes).unwrap_err().valid_up_to(), 3);
+    }
+
+    #[test]
+    fn test_record_equality() {
+        // the same fields on different lines, and trimmed from different bytes
+        let dialect = Dialect::builder().trim(Trim::All).build();
+        let mut p = Parser::new(dialect, reader_from_str("a,b
+ a , b
+a,c
+"));
+        let records: Vec<_> = (0..3).map(|_| p.read_line().unwrap().to_byte_record()).collect();
+        assert_ne!(records[0].position(), records[1].position());
+        assert_eq!(records[0], records[1]);
+        assert_ne!(records[0], records[2]);
+        let strings: Vec<_> = records.into_iter().map(|record| StringRecord::from_byte_record(record).unwrap()).collect();
+        assert_eq!(strings[0], strings[1]);
+        assert_ne!(strings[1], strings[2]);
+    }
+
+    #[test]
+    fn test_unescaped() {
+        let line = "plain,\"quoted\",\"a \"\"b\"\" c\",\"\",\"\"\"\"\"\",x\"y\"z,\"\u{e9}\"\"\"\n";
+        let mut p = Parser::new(default_dialect(), reader_from_str(line));
+        let record = p.read_line().unwrap();
+        let fields: Vec<Cow<[u8]>> = record.iter_unescaped().collect();
+        assert_eq!(fields, vec![&b"plain"[..], b"quoted", b"a \"b\" c", b"", b"\"\"", b"x\"y\"z", "\u{e9}\"".as_bytes()]);
+        // only fields with something to collapse are copied
+        assert!(matches!(record.unescaped(0), Cow::Borrowed(_)));
+        assert!(matches!(record.unescaped(1), Cow::Borrowed(_)));
+        assert!(matches!(record.unescaped(2), Cow::Owned(_)));
+        assert!(matches!(record.unescaped(5), Cow::Borrowed(_)));
+        assert_eq!(record.unescaped_str(6).unwrap(), "\u{e9}\"");
+        // the raw span is unchanged
+        assert_eq!(&record[2], "\"a \"\"b\"\" c\"");
+    }
+
+    #[test]
+    fn test_unescaped_long_field() {
+        // a doubled quote far into the field and one on each side of a word boundary
+        let value = format!("{}\"\"{}\"\"", "a".repeat(70), "b".repeat(5));
+        let line = format!("\"{}\",1\n", value);
+        let mut p = Parser::new(default_dialect(), reader_from_str(&line));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.unescaped_str(0).unwrap(), format!("{}\"{}\"", "a".repeat(70), "b".repeat(5)));
+        assert_eq!(record.unescaped_str(1).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_unescaped_escapechar() {
+        let dialect = Dialect::builder().escape(Some(b'\\')).build();
+        let line = "a\\,b,\"c\\\"d\",e\\\\\n";
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        let record = p.read_line().unwrap();
+        let fields: Vec<Cow<[u8]>> = record.iter_unescaped().collect();
+        assert_eq!(fields, vec![&b"a,b"[..], b"c\"d", b"e\\"]);
+
+        // with quoting off, quotes are kept
+        let mut p = Parser::new(Dialect::mysql_dump(), reader_from_str("\"a\"\tb\\\tc\n"));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.unescaped(0), &b"\"a\""[..]);
+        assert_eq!(record.unescaped(1), &b"b\tc"[..]);
+    }
+
+    #[test]
+    fn test_unescaped_invalid_utf8() {
+        let mut p = Parser::new(default_dialect(), reader_from_bytes(b"\"\xff\"\"\",\xfe\n"));
+        let record = p.read_line().unwrap();
+        assert_eq!(record.unescaped(0), &b"\xff\""[..]);
+        assert!(record.unescaped_str(0).is_err());
+        assert!(record.unescaped_str(1).is_err());
+    }
+
+    fn trim_dialect(trim: Trim) -> Dialect {
+        Dialect::builder().trim(trim).build()
+    }
+
+    #[test]
+    fn test_trim_all() {
+        let line = "  a  ,\tb\t, c d ,   ,\" q \"  ,\n x ,y \r\n";
+        let mut p = Parser::new(trim_dialect(Trim::All), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b", "c d", "", "\" q \"", ""]);
+        assert_eq!(p.read_line().unwrap(), vec!["x", "y"]);
+        assert!(p.read_line().is_none());
+    }
+
+    #[test]
+    fn test_trim_headers_and_fields() {
+        let line = " a , b \n 1 , 2 \n";
+        let mut p = Parser::new(trim_dialect(Trim::Headers), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        assert_eq!(p.read_line().unwrap(), vec![" 1 ", " 2 "]);
+
+        let mut p = Parser::new(trim_dialect(Trim::Fields), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec![" a ", " b "]);
+        assert_eq!(p.read_line().unwrap(), vec!["1", "2"]);
+
+        let mut p = Parser::new(trim_dialect(Trim::None), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec![" a ", " b "]);
+    }
+
+    #[test]
+    fn test_trim_across_chunks() {
+        // runs of whitespace ending at every offset around the chunk boundary, before a delimiter,
+        // a multi-byte delimiter, and \r\n
+        for split in 56..68 {
+            let field = format!("{}x{}", " ".repeat(split - 10), " ".repeat(9));
+            let line = format!("{},y\n", field);
+            let mut p = Parser::new(trim_dialect(Trim::All), reader_from_str(&line));
+            assert_eq!(p.read_line().unwrap(), vec!["x", "y"], "split {}", split);
+
+            let line = format!("{}~|~ y \r\n", field);
+            let dialect = Dialect::builder().delimiter_str("~|~").terminator(Terminator::Crlf).trim(Trim::All).build();
+            let mut p = Parser::new(dialect, reader_from_str(&line));
+            assert_eq!(p.read_line().unwrap(), vec!["x", "y"], "split {}", split);
+
+            let line = format!("a,{}\r\n", field);
+            let mut p = Parser::new(Dialect { terminator: Terminator::Crlf, ..trim_dialect(Trim::All) }, reader_from_str(&line));
+            assert_eq!(p.read_line().unwrap(), vec!["a", "x"], "split {}", split);
+        }
+    }
+
+    #[test]
+    fn test_trim_tab_delimiter() {
+        let line = " a \t\t\tb \n";
+        let dialect = Dialect { trim: Trim::All, ..Dialect::excel_tab() };
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "", "", "b"]);
+    }
+
+    #[test]
+    fn test_trim_padded_delimiter() {
+        // only the spaces of matched delimiters are kept out of the whitespace
+        let dialect = Dialect::builder().delimiter_str(" | ").trim(Trim::All).build();
+        let mut p = Parser::new(dialect.clone(), reader_from_str("a | b |  c \n |  | \n"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(p.read_line().unwrap(), vec!["", "", ""]);
+
+        // the delimiter straddles a chunk boundary right after a closing quote
+        let strict = Dialect { strict: true, ..dialect };
+        for split in 60..64 {
+            let field = format!("\"{}\"", "a".repeat(split - 2));
+            let mut p = Parser::new(strict.clone(), reader_from_str(&format!("{} | b \n", field)));
+            assert_eq!(p.read_record().unwrap().unwrap(), vec![field.as_str(), "b"], "split {}", split);
+        }
+
+        // a field start isn't moved onto the delimiter that ends it
+        let dialect = Dialect::builder().delimiter_str(" | ").skipinitialspace(true).build();
+        let mut p = Parser::new(dialect, reader_from_str("a |  | b\n"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn test_trim_ascii_whitespace() {
+        // a \r is data in Lf mode, and a \n with a custom terminator
+        let dialect = Dialect::builder().trim(Trim::All).terminator(Terminator::Lf).build();
+        let mut p = Parser::new(dialect, reader_from_str(" a \r,\x0C\tb\r\n"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b"]);
+        let dialect = Dialect::builder().trim(Trim::All).terminator(Terminator::Byte(b';')).build();
+        let mut p = Parser::new(dialect, reader_from_str("\na\n,\n;b ,\n;"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", ""]);
+        assert_eq!(p.read_line().unwrap(), vec!["b", ""]);
+
+        // in Crlf mode only a lone \r or \n is trimmed
+        let dialect = Dialect::builder().trim(Trim::All).terminator(Terminator::Crlf).build();
+        let mut p = Parser::new(dialect.clone(), reader_from_str("a \r, \nb ,\r\n c \r\n"));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "b", ""]);
+        assert_eq!(p.read_line().unwrap(), vec!["c"]);
+        // the \r\n straddles a chunk boundary
+        let line = format!("{}, \r\nx\r\n", "a".repeat(61));
+        let mut p = Parser::new(dialect, reader_from_str(&line));
+        assert_eq!(p.read_line().unwrap(), vec!["a".repeat(61).as_str(), ""]);
+        assert_eq!(p.read_line().unwrap(), vec!["x"]);
+    }
+
+    #[test]
+    fn test_trim_strict_quotes() {
+        let line = " \"a\" , \"b\"\t\n";
+        let dialect = Dialect::builder().strict(true).trim(Trim::All).build();
+        let mut p = Parser::new(dialect, reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["\"a\"", "\"b\""]);
+    }
+
+    #[test]
+    fn test_trim_strict_data_after_closing_quote() {
+        // whitespace after a closing quote is only trimmed if a separator follows it
+        let dialect = Dialect::builder().strict(true).trim(Trim::All).build();
+        let mut p = Parser::new(dialect.clone(), reader_from_str("\"a\" b,c\nd\n"));
+        let err = p.read_record().unwrap_err();
+        assert_eq!((err.kind(), err.position().byte), (ErrorKind::DataAfterClosingQuote, 4));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["d"]);
+
+        // the whitespace runs into the next chunk
+        let field = format!("\"{}\"", "a".repeat(60));
+        let mut p = Parser::new(dialect.clone(), reader_from_str(&format!("{} \t  ,b\n{} \t  b\n", field, field)));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec![field.as_str(), "b"]);
+        let err = p.read_record().unwrap_err();
+        assert_eq!((err.kind(), err.position().byte), (ErrorKind::DataAfterClosingQuote, 2 * field.len() as u64 + 11));
+
+        // up to the end of the input
+        let mut p = Parser::new(dialect, reader_from_str("x,\"a\"  "));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["x", "\"a\""]);
+    }
+
+    #[test]
+    fn test_trim_example_files() {
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            let mut p = Parser::new(trim_dialect(Trim::All), AlignedBuffer::new(&File::open(path).unwrap()).unwrap());
+            let mut reference = Parser::new(default_dialect(), AlignedBuffer::new(&File::open(path).unwrap()).unwrap());
+            while let Some(expected) = reference.read_line() {
+                let ours = p.read_line().unwrap();
+                assert_eq!(ours.len(), expected.len());
+                for i in 0..ours.len() {
+                    assert_eq!(&ours[i], expected[i].trim_ascii());
+                }
+            }
+            assert!(p.read_line().is_none());
+        }
+    }
+
+    #[test]
+    fn test_dialect_builder() {
+        let dialect = Dialect::builder()
+            .delimiter(b';')
+            .quote(b'\'')
+            .skipinitialspace(true)
+            .escape(Some(b'\\'))
+            .strict(true)
+            .terminator(Terminator::Lf)
+            .comment(Some(b'#'))
+            .build();
+        let mut expected = Dialect::new(';', '\'', true, true);
+        expected.escapechar = Some('\\');
+        expected.terminator = Terminator::Lf;
+        expected.comment = Some(b'#');
+        assert_eq!(dialect, expected);
+        assert_eq!(Dialect::builder().build(), default_dialect());
+        assert_eq!(Dialect::builder().delimiter_str("||").build().delimiter, "||");
+    }
+
+    #[test]
+    fn test_dialect_builder_conflicts() {
+        let conflicts = [
+            Dialect::builder().escape(Some(b'"')),
+            Dialect::builder().delimiter_str("a\"b"),
+            Dialect::builder().comment(Some(b',')),
+            Dialect::builder().terminator(Terminator::Byte(b'#')).comment(Some(b'#')),
+            Dialect::builder().delimiter(b'\r'),
+            Dialect::builder().quote(b'\n').terminator(Terminator::Lf),
+        ];
+        for builder in conflicts {
+            assert!(std::panic::catch_unwind(|| builder.build()).is_err());
+        }
+        // a quote that is never used doesn't conflict, nor does a \r that is data
+        Dialect::builder().quote(b',').quoting(QuoteStyle::Never).build();
+        Dialect::builder().delimiter(b'\r').terminator(Terminator::Lf).build();
+    }
+
+    #[test]
+    fn test_preset_excel() {
+        let line = "a,\"b,\r\n\"\"c\"\"\", d\r\ne\rf\n";
+        let mut p = Parser::new(Dialect::excel(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "\"b,\r\n\"\"c\"\"\"", " d"]);
+        assert_eq!(p.read_line().unwrap(), vec!["e"]);
+        assert_eq!(p.read_line().unwrap(), vec!["f"]);
+
+        let line = "a\t\"b\tc\"\t,d\r\n";
+        let mut p = Parser::new(Dialect::excel_tab(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "\"b\tc\"", ",d"]);
+    }
+
+    #[test]
+    fn test_preset_unix() {
+        let line = "a,\"b\nc\"\r\nd\n";
+        let mut p = Parser::new(Dialect::unix(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["a", "\"b\nc\"\r"]);
+        assert_eq!(p.read_line().unwrap(), vec!["d"]);
+    }
+
+    #[test]
+    fn test_preset_rfc4180() {
+        let line = "a,b\nc\r\n\"d\"\"\",e\r\nf\"g\r\n";
+        let mut p = Parser::new(Dialect::rfc4180(), reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["a", "b\nc"]);
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["\"d\"\"\"", "e"]);
+        assert_eq!(p.read_record().err().unwrap().kind(), ErrorKind::QuoteInUnquotedField);
+    }
+
+    #[test]
+    fn test_preset_postgres_csv() {
+        let line = "1,\"x,y\",\n2,\"x\"y\n";
+        let mut p = Parser::new(Dialect::postgres_csv(), reader_from_str(line));
+        assert_eq!(p.read_record().unwrap().unwrap(), vec!["1", "\"x,y\"", ""]);
+        assert_eq!(p.read_record().err().unwrap().kind(), ErrorKind::DataAfterClosingQuote);
+    }
+
+    #[test]
+    fn test_preset_mysql_dump() {
+        let line = "1\t\"a\tb\\\tc\td\\\ne\r\n2\t\\N\n";
+        let mut p = Parser::new(Dialect::mysql_dump(), reader_from_str(line));
+        assert_eq!(p.read_line().unwrap(), vec!["1", "\"a", "b\\\tc", "d\\\ne\r"]);
+        assert_eq!(p.read_line().unwrap(), vec!["2", "\\N"]);
+    }
+
+    #[test]
+    fn test_sniff_comma_quoted() {
+        let data = "name,age,city\n\"Smith, J\",42,\"New York, NY\"\nDoe,7,Paris\nRoe,19,\"Oslo\"\n";
+        let result = sniff(&reader_from_str(data), 4096);
+        assert_eq!(result.dialect.delimiter, ",");
+        assert_eq!(result.dialect.quotechar, '"');
+        assert_eq!(result.dialect.terminator, Terminator::Lf);
+        assert!(!result.dialect.skipinitialspace);
+        assert!(result.has_header);
+        assert_eq!(result.confidence, 1.0);
+
+        // the dialect is ready to use
+        let mut p = Parser::new(result.dialect, reader_from_str(data));
+        assert_eq!(p.read_line().unwrap(), vec!["name", "age", "city"]);
+        assert_eq!(p.read_line().unwrap(), vec!["\"Smith, J\"", "42", "\"New York, NY\""]);
+    }
+
+    #[test]
+    fn test_sniff_semicolon_single_quotes() {
+        let data = "1;'a;b';2.5\r\n2;'c';3\r\n3;'d;e;f';1e3\r\n";
+        let result = sniff(&reader_from_str(data), 4096);
+        assert_eq!(result.dialect.delimiter, ";");
+        assert_eq!(result.dialect.quotechar, '\'');
+        assert_eq!(result.dialect.terminator, Terminator::Crlf);
+        assert!(!result.has_header);
+    }
+
+    #[test]
+    fn test_sniff_tab_skipinitialspace() {
+        let data = "id\t count\t code\n1\t 20\t ab\n2\t 31\t cd\n3\t 4\t ef\n";
+        let result = sniff(&reader_from_str(data), 4096);
+        assert_eq!(result.dialect.delimiter, "\t");
+        assert!(result.dialect.skipinitialspace);
+        assert!(result.has_header);
+    }
+
+    #[test]
+    fn test_sniff_partial_sample() {
+        // the sample ends halfway through a row, which is left out
+        let data = "a|b|c\n1|2|3\n4|5|6\n7|8";
+        let result = sniff(&reader_from_str(data), data.len() - 1);
+        assert_eq!(result.dialect.delimiter, "|");
+        assert_eq!(result.confidence, 1.0);
+        assert_eq!(sniff(&reader_from_str(data), data.len()).confidence, 0.75);
+    }
+
+    #[test]
+    fn test_sniff_single_column() {
+        let result = sniff(&reader_from_str("one\ntwo\nthree\n"), 4096);
+        assert_eq!(result.dialect, default_dialect());
+        assert_eq!(result.confidence, 0.0);
+    }
+
+    #[test]
+    fn test_sniff_example_files() {
+        for (path, has_header) in [("examples/nfl.csv", true), ("examples/EDW.TEST_CAL_DT.csv", false)].iter() {
+            let result = sniff(&AlignedBuffer::new(&File::open(path).unwrap()).unwrap(), 64 * 1024);
+            assert_eq!(result.dialect.delimiter, ",", "{}", path);
+            assert_eq!(result.has_header, *has_header, "{}", path);
+            assert!(result.confidence > 0.9, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let dialects = || [default_dialect(), Dialect::new(',', '\"', true, true), Dialect::new(';', '\'', false, false), Dialect::new("0,", '\"', false, false), Dialect { quoting: QuoteStyle::Never, ..default_dialect() }, Dialect { trim: Trim::All, ..default_dialect() }];
+        for path in ["examples/nfl.csv", "examples/EDW.TEST_CAL_DT.csv"].iter() {
+            for backend in Backend::PREFERENCE.into_iter().filter(|b| b.is_supported()) {
+                for (dialect, reference) in dialects().into_iter().zip(dialects()) {
+                    let mut p = Parser::with_backend(dialect, AlignedBuffer::new(&File::open(path).unwrap()).unwrap(), backend);
+                    let mut reference = Parser::with_backend(reference, AlignedBuffer::new(&File::open(path).unwrap()).unwrap(), Backend::Scalar);
+                    assert_eq!(p.backend(), backend);
+                    let mut counter = 0;
+                    while let Some(expected) = reference.read_line() {
+                        let ours = p.read_line().unwrap();
+                        assert_eq!(ours.len(), expected.len(), "{:?}: record {} of {}", backend, counter, path);
+                        for i in 0..ours.len() {
+                            assert_eq!(&ours[i], &expected[i], "{:?}: record {} of {}", backend, counter, path);
+                        }
+                        counter += 1;
+                    }
+                    assert!(p.read_line().is_none());
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_parse_file() {
         let file = File::open("examples/customers-2000000.csv").unwrap();
         let mut p = Parser::new(default_dialect(), AlignedBuffer::new(&file).unwrap());
-        while let Some(mut record) = p.read_line() {
+        while let Some(record) = p.read_line() {
             for field in record.iter() {
                 let _ = field.len();
             }
@@ -112,7 +1369,7 @@ mod tests {
         fn parse_file() {
             let file = File::open("examples/nfl.csv").unwrap();
             let mut p = Parser::new(default_dialect(), AlignedBuffer::new(&file).unwrap());
-            while let Some(mut record) = p.read_line() {
+            while let Some(record) = p.read_line() {
                 for field in record.iter() {
                     let _ = field.len();
                 }